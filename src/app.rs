@@ -1,12 +1,15 @@
-use std::time::SystemTime;
+use std::{io, time::SystemTime};
 
 use once_cell::sync::OnceCell;
 
-use crate::utils;
+use crate::{
+    engine::tls::{self, TlsTunnel},
+    utils::{self, sign},
+};
 
 static mut APPONE: OnceCell<Application> = OnceCell::new();
 
-pub const VERSION: &str = "0.3.3";
+pub const VERSION: &str = "0.4.0";
 pub struct Application {
     ctx: ruisutil::Context,
     pub conf: Option<crate::entity::conf::ServerConf>,
@@ -14,6 +17,13 @@ pub struct Application {
     pub keys: Option<String>,
     pub apiaddrs: String,
     pub apikeys: Option<String>,
+    pub tls_ca: Option<String>,
+    pub tls_name: Option<String>,
+    pub apitls_ca: Option<String>,
+    pub apitls_name: Option<String>,
+    tlsaddrs: Option<String>,
+    apitlsaddrs: Option<String>,
+    pub node_name: Option<String>,
 
     pub server_case: Option<crate::engine::ServerCase>,
 }
@@ -53,6 +63,17 @@ impl Application {
                 },
             },
         };
+        let (tls_ca, tls_name) = match &conf {
+            None => (None, None),
+            Some(v) => (v.server.tls_ca.clone(), v.server.tls_name.clone()),
+        };
+        let (apitls_ca, apitls_name) = match &conf {
+            None => (None, None),
+            Some(v) => match &v.api_server {
+                None => (None, None),
+                Some(vc) => (vc.tls_ca.clone(), vc.tls_name.clone()),
+            },
+        };
 
         let app = Self {
             ctx: ruisutil::Context::background(None),
@@ -91,6 +112,13 @@ impl Application {
             } else {
                 None
             },
+            tls_ca: tls_ca.or_else(|| std::env::var("HBPROXY_TLS_CA").ok()),
+            tls_name,
+            apitls_ca: apitls_ca.or_else(|| std::env::var("HBPROXY_APITLS_CA").ok()),
+            apitls_name,
+            tlsaddrs: None,
+            apitlsaddrs: None,
+            node_name: None,
 
             server_case: None,
         };
@@ -117,43 +145,81 @@ impl Application {
         Self::get().ctx.clone()
     }
 
+    /// start the local tls tunnels,requests then dial them instead of the server addrs.
+    pub async fn start_tls() -> io::Result<()> {
+        let app = Self::get_mut();
+        if let Some(vs) = &app.tls_ca {
+            let tn = TlsTunnel::new(false, app.tls_name.clone(), tls::load_connector(vs)?);
+            app.tlsaddrs = Some(tn.start(app.ctx.clone()).await?);
+        }
+        if let Some(vs) = &app.apitls_ca {
+            let tn = TlsTunnel::new(true, app.apitls_name.clone(), tls::load_connector(vs)?);
+            app.apitlsaddrs = Some(tn.start(app.ctx.clone()).await?);
+        }
+        Ok(())
+    }
+
     pub fn new_reqs(ctrl: i32, cmds: &str) -> hbtp::Request {
         Self::new_req(ctrl, cmds, true)
     }
     pub fn new_req(ctrl: i32, cmds: &str, is_api: bool) -> hbtp::Request {
+        Self::new_req_with(ctrl, cmds, is_api, &[], None)
+    }
+    pub fn new_reqs_with(
+        ctrl: i32,
+        cmds: &str,
+        args: &[(&str, &str)],
+        bodys: Option<&[u8]>,
+    ) -> hbtp::Request {
+        Self::new_req_with(ctrl, cmds, true, args, bodys)
+    }
+    /// args and bodys are covered by the signature,so they must be given here.
+    pub fn new_req_with(
+        ctrl: i32,
+        cmds: &str,
+        is_api: bool,
+        args: &[(&str, &str)],
+        bodys: Option<&[u8]>,
+    ) -> hbtp::Request {
         let addrs = if is_api {
-            &Self::get().apiaddrs
+            match &Self::get().apitlsaddrs {
+                Some(v) => v,
+                None => &Self::get().apiaddrs,
+            }
         } else {
-            &Self::get().addrs
+            match &Self::get().tlsaddrs {
+                Some(v) => v,
+                None => &Self::get().addrs,
+            }
         };
         let keys = if is_api {
             &Self::get().apikeys
         } else {
             &Self::get().keys
         };
+        // node requests name their node so the server can pick its key
+        let mut args = args.to_vec();
+        if let (false, Some(vs)) = (is_api, &Self::get().node_name) {
+            args.push(("node", vs.as_str()));
+        }
+        let args = &args[..];
         let mut req = hbtp::Request::new(addrs.as_str(), ctrl);
         req.command(cmds);
+        for (k, v) in args {
+            req.add_arg(k, v);
+        }
         if let Some(vs) = keys {
             let tms = ruisutil::strftime(SystemTime::now(), "%+");
             let rands = ruisutil::random(20);
-            let sign = ruisutil::md5str(format!(
-                "{}{}{}{}",
-                cmds,
-                tms.as_str(),
-                rands.as_str(),
-                vs.as_str()
-            ));
-            /* log::debug!(
-                "sginos:{}{}{}{}",
-                cmds,
-                tms.as_str(),
-                rands.as_str(),
-                vs.as_str()
-            );
-            log::debug!("signs:{}",sign.as_str()); */
+            let argks: Vec<&str> = args.iter().map(|(k, _)| *k).collect();
+            let keys = argks.join(",");
+            let texts = sign::sign_texts(cmds, &tms, &rands, &keys, args, bodys);
+            let sign = sign::hmac_sign(vs.as_str(), texts.as_str());
             req.add_arg("times", tms.as_str());
             req.add_arg("random", rands.as_str());
             req.add_arg("sign", sign.as_str());
+            req.add_arg("sign_ver", sign::SIGN_VER);
+            req.add_arg("sign_args", keys.as_str());
         }
         req
     }
@@ -0,0 +1,106 @@
+use crate::{app::Application, entity::proxy::BudgetListRep};
+
+pub async fn runs<'a>(args: &clap::ArgMatches<'a>) -> i32 {
+    if let Some(v) = args.subcommand_matches("ls") {
+        lss(v).await
+    } else if let Some(v) = args.subcommand_matches("set") {
+        sets(v).await
+    } else {
+        -2
+    }
+}
+
+async fn lss<'a>(_: &clap::ArgMatches<'a>) -> i32 {
+    let mut req = Application::new_reqs(3, "BudgetList");
+    match req.dors(None, None).await {
+        Err(e) => {
+            eprintln!("request do err:{}", e);
+            return -2;
+        }
+        Ok(res) => {
+            if res.get_code() == hbtp::ResCodeOk {
+                let data: BudgetListRep = match res.body_json() {
+                    Err(e) => {
+                        eprintln!("response body err:{}", e);
+                        return -3;
+                    }
+                    Ok(v) => v,
+                };
+                println!("{:<10}{:<30}{:>12}{:>12}", "Scope", "Name", "Up(kb/s)", "Down(kb/s)");
+                for v in &data.list {
+                    let (ups, downs) = match &v.limit {
+                        None => ("-".to_string(), "-".to_string()),
+                        Some(v) => (v.up.to_string(), v.down.to_string()),
+                    };
+                    println!(
+                        "{:<10}{:<30}{:>12}{:>12}",
+                        v.scope.as_str(),
+                        v.name.as_str(),
+                        ups.as_str(),
+                        downs.as_str()
+                    );
+                }
+            } else {
+                if let Some(bs) = res.get_bodys() {
+                    if let Ok(vs) = std::str::from_utf8(&bs[..]) {
+                        eprintln!("res err:{}", vs);
+                    }
+                }
+                return -3;
+            }
+        }
+    }
+    0
+}
+
+async fn sets<'a>(args: &clap::ArgMatches<'a>) -> i32 {
+    let scopes = if let Some(vs) = args.value_of("scope") {
+        vs
+    } else {
+        println!("scope is required");
+        return -1;
+    };
+    let names = args.value_of("name").unwrap_or("");
+    if scopes != "server" && names.is_empty() {
+        println!("name is required");
+        return -1;
+    }
+    let ups = args.value_of("up").unwrap_or("0");
+    let downs = args.value_of("down").unwrap_or("0");
+    let bursts = args.value_of("burst").unwrap_or("0");
+    let mut req = Application::new_reqs_with(
+        3,
+        "BudgetSet",
+        &[
+            ("scope", scopes),
+            ("name", names),
+            ("up", ups),
+            ("down", downs),
+            ("burst", bursts),
+        ],
+        None,
+    );
+    match req.dors(None, None).await {
+        Err(e) => {
+            eprintln!("request do err:{}", e);
+            return -2;
+        }
+        Ok(res) => {
+            if res.get_code() == hbtp::ResCodeOk {
+                if let Some(bs) = res.get_bodys() {
+                    if let Ok(vs) = std::str::from_utf8(&bs[..]) {
+                        println!("set:{}", vs);
+                    }
+                }
+            } else {
+                if let Some(bs) = res.get_bodys() {
+                    if let Ok(vs) = std::str::from_utf8(&bs[..]) {
+                        eprintln!("res err:{}", vs);
+                    }
+                }
+                return -3;
+            }
+        }
+    }
+    0
+}
@@ -0,0 +1,92 @@
+use crate::{app::Application, entity::proxy::ConnListRep};
+
+pub async fn runs<'a>(args: &clap::ArgMatches<'a>) -> i32 {
+    if let Some(v) = args.subcommand_matches("ls") {
+        lss(v).await
+    } else if let Some(v) = args.subcommand_matches("kill") {
+        kills(v).await
+    } else {
+        -2
+    }
+}
+
+async fn lss<'a>(args: &clap::ArgMatches<'a>) -> i32 {
+    let mut req = match args.value_of("rule") {
+        None => Application::new_reqs(3, "ConnList"),
+        Some(vs) => Application::new_reqs_with(3, "ConnList", &[("rule", vs)], None),
+    };
+    match req.dors(None, None).await {
+        Err(e) => {
+            eprintln!("request do err:{}", e);
+            return -2;
+        }
+        Ok(res) => {
+            if res.get_code() == hbtp::ResCodeOk {
+                let data: ConnListRep = match res.body_json() {
+                    Err(e) => {
+                        eprintln!("response body err:{}", e);
+                        return -3;
+                    }
+                    Ok(v) => v,
+                };
+                println!(
+                    "{:<22}{:<20}{:<30}{:<24}{:>10}{:>12}{:>12}",
+                    "ID", "Rule", "Goto", "Client", "Times", "Up", "Down"
+                );
+                for v in &data.list {
+                    println!(
+                        "{:<22}{:<20}{:<30}{:<24}{:>10}{:>12}{:>12}",
+                        v.id.as_str(),
+                        v.rule.as_deref().unwrap_or("-"),
+                        v.goto.as_str(),
+                        v.client.as_deref().unwrap_or("-"),
+                        format!("{}s", v.times),
+                        crate::utils::mybytes(v.up),
+                        crate::utils::mybytes(v.down)
+                    );
+                }
+            } else {
+                if let Some(bs) = res.get_bodys() {
+                    if let Ok(vs) = std::str::from_utf8(&bs[..]) {
+                        eprintln!("res err:{}", vs);
+                    }
+                }
+                return -3;
+            }
+        }
+    }
+    0
+}
+
+async fn kills<'a>(args: &clap::ArgMatches<'a>) -> i32 {
+    let ids = if let Some(vs) = args.value_of("id") {
+        vs
+    } else {
+        println!("id is required");
+        return -1;
+    };
+    let mut req = Application::new_reqs_with(3, "ConnKill", &[("id", ids)], None);
+    match req.dors(None, None).await {
+        Err(e) => {
+            eprintln!("request do err:{}", e);
+            return -2;
+        }
+        Ok(res) => {
+            if res.get_code() == hbtp::ResCodeOk {
+                if let Some(bs) = res.get_bodys() {
+                    if let Ok(vs) = std::str::from_utf8(&bs[..]) {
+                        println!("kill:{}", vs);
+                    }
+                }
+            } else {
+                if let Some(bs) = res.get_bodys() {
+                    if let Ok(vs) = std::str::from_utf8(&bs[..]) {
+                        eprintln!("res err:{}", vs);
+                    }
+                }
+                return -3;
+            }
+        }
+    }
+    0
+}
@@ -1,3 +1,5 @@
+mod budget;
+mod conn;
 mod node;
 mod proxy;
 mod server;
@@ -5,6 +7,10 @@ mod server;
 use crate::{app::Application, utils};
 
 pub async fn cmds(cmdargs: clap::ArgMatches<'static>) -> i32 {
+    if let Err(e) = Application::start_tls().await {
+        log::error!("start tls err:{}", e);
+        return -1;
+    }
     if let Some(v) = cmdargs.subcommand_matches("test") {
         if v.is_present("debug") {
             println!("Printing debug info...");
@@ -18,6 +24,10 @@ pub async fn cmds(cmdargs: clap::ArgMatches<'static>) -> i32 {
         node::runs(v).await
     } else if let Some(v) = cmdargs.subcommand_matches("proxy") {
         proxy::runs(v).await
+    } else if let Some(v) = cmdargs.subcommand_matches("budget") {
+        budget::runs(v).await
+    } else if let Some(v) = cmdargs.subcommand_matches("conn") {
+        conn::runs(v).await
     } else if let Some(v) = cmdargs.subcommand_matches("version") {
         if v.is_present("remote") {
             match utils::remote_version(Application::new_req(1, "version", false)).await {
@@ -4,7 +4,7 @@ use async_std::task;
 
 use crate::{
     app::Application,
-    engine::{self, NodeClientCfg},
+    engine::{self, NodeClientCfg, NodeVisitCfg},
     entity::node::NodeListRep,
     utils,
 };
@@ -36,10 +36,45 @@ async fn joins<'a>(args: &clap::ArgMatches<'a>) -> i32 {
         }
     };
 
+    let mut visits = Vec::new();
+    if let Some(vs) = args.values_of("visit") {
+        for v in vs {
+            let vls: Vec<&str> = v.splitn(2, "=").collect();
+            if vls.len() != 2 || vls[0].is_empty() || vls[1].is_empty() {
+                eprintln!("visit err:{}", v);
+                return -1;
+            }
+            visits.push(NodeVisitCfg {
+                bind: if vls[0].contains(":") {
+                    vls[0].to_string()
+                } else {
+                    format!("127.0.0.1:{}", vls[0])
+                },
+                rule: vls[1].to_string(),
+            });
+        }
+    }
+
+    let pool = match args.value_of("pool") {
+        None => 0,
+        Some(vs) => match vs.parse::<usize>() {
+            Ok(v) => v,
+            Err(_) => {
+                eprintln!("pool err:{}", vs);
+                return -1;
+            }
+        },
+    };
+
+    Application::get_mut().node_name = Some(names.to_string());
     let cfg = NodeClientCfg {
         name: names.to_string(),
         token: None,
         remote_version: String::new(),
+        mux: args.is_present("mux"),
+        visits,
+        visit_key: args.value_of("visit-key").map(|v| v.to_string()),
+        pool,
     };
     while !Application::context().done() {
         if let Err(e) = engine::NodeClient::runs(&cfg).await {
@@ -73,8 +108,8 @@ async fn lss<'a>(_: &clap::ArgMatches<'a>) -> i32 {
                     Ok(v) => v,
                 };
                 println!(
-                    "{:<30}{:<25}{:^10}{:^12}{:^10}",
-                    "Name", "Addr", "Online", "Duration", "Version"
+                    "{:<30}{:<25}{:^10}{:^12}{:^10}{:<28}",
+                    "Name", "Addr", "Online", "Duration", "Version", "Traffic"
                 );
                 for v in &data.list {
                     let frms = match &v.addrs {
@@ -89,13 +124,18 @@ async fn lss<'a>(_: &clap::ArgMatches<'a>) -> i32 {
                         None => utils::mytimes(v.online_times),
                         Some(v) => format!("OUT:{}", utils::mytimes(v)),
                     };
+                    let trfs = match &v.traffic {
+                        None => "-".to_string(),
+                        Some(v) => v.trafficstr(),
+                    };
                     println!(
-                        "{:<30}{:<25}{:^10}{:^12}{:^10}",
+                        "{:<30}{:<25}{:^10}{:^12}{:^10}{:<28}",
                         v.name.as_str(),
                         frms.as_str(),
                         v.online,
                         tms,
                         vers.as_str(),
+                        trfs.as_str(),
                     );
                 }
             } else {
@@ -2,7 +2,8 @@ use crate::{
     app::Application,
     entity::{
         node::ProxyGoto,
-        proxy::{ProxyListRep, RuleConfReq, RuleConfGoto},
+        proxy::{ProxyAclReq, ProxyListRep, ProxyReloadRep, RuleConfReq, RuleConfGoto},
+        util::ProxyLimit,
     },
 };
 
@@ -19,6 +20,8 @@ pub async fn runs<'a>(args: &clap::ArgMatches<'a>) -> i32 {
         stops(v).await
     } else if let Some(v) = args.subcommand_matches("rm") {
         rms(v).await
+    } else if let Some(v) = args.subcommand_matches("acl") {
+        acls(v).await
     } else {
         -2
     }
@@ -33,10 +36,18 @@ async fn reloads<'a>(_: &clap::ArgMatches<'a>) -> i32 {
         }
         Ok(res) => {
             if res.get_code() == hbtp::ResCodeOk {
-                if let Some(bs) = res.get_bodys() {
-                    if let Ok(vs) = std::str::from_utf8(&bs[..]) {
-                        println!("reload:{}", vs);
+                let data: ProxyReloadRep = match res.body_json() {
+                    Err(e) => {
+                        eprintln!("response body err:{}", e);
+                        return -3;
                     }
+                    Ok(v) => v,
+                };
+                println!("added:{}", data.added.join(","));
+                println!("removed:{}", data.removed.join(","));
+                println!("changed:{}", data.changed.join(","));
+                for v in &data.errs {
+                    eprintln!("err:{}", v);
                 }
             } else {
                 if let Some(bs) = res.get_bodys() {
@@ -62,6 +73,16 @@ async fn adds<'a>(args: &clap::ArgMatches<'a>) -> i32 {
         eprintln!("bind?");
         return -1;
     };
+    let protocol = match args.value_of("protocol") {
+        None => None,
+        Some(vs) => match vs {
+            "tcp" | "udp" => Some(vs.to_string()),
+            _ => {
+                println!("protocol err:{}", vs);
+                return -2;
+            }
+        },
+    };
     let gotos = if let Some(vs) = args.values_of("goto") {
         vs
     } else {
@@ -109,11 +130,53 @@ async fn adds<'a>(args: &clap::ArgMatches<'a>) -> i32 {
             proxy_host: vls[0].to_string(),
             proxy_port: vport,
             limit: None,
+            weight: None,
+            proxy_protocol: None,
+            compress: if args.is_present("compress") {
+                Some(true)
+            } else {
+                None
+            },
+            encrypt: if args.is_present("encrypt") {
+                Some(true)
+            } else {
+                None
+            },
         })
     }
 
+    let mut caps = [None; 3];
+    for (i, k) in ["max-conns", "max-conns-per-ip", "conn-rate"].iter().enumerate() {
+        if let Some(vs) = args.value_of(k) {
+            match vs.parse::<u32>() {
+                Err(_) => {
+                    println!("{} err:{}", k, vs);
+                    return -2;
+                }
+                Ok(v) => caps[i] = Some(v),
+            }
+        }
+    }
+    let mut budget = ProxyLimit {
+        up: 0,
+        down: 0,
+        burst: None,
+    };
+    for (k, v) in [("budget-up", &mut budget.up), ("budget-down", &mut budget.down)] {
+        if let Some(vs) = args.value_of(k) {
+            match vs.parse::<usize>() {
+                Err(_) => {
+                    println!("{} err:{}", k, vs);
+                    return -2;
+                }
+                Ok(n) => *v = n,
+            }
+        }
+    }
+
     let data = RuleConfReq {
         name: names,
+        protocol,
         bind_host: if bindls[0].is_empty() {
             "0.0.0.0".to_string()
         } else {
@@ -121,9 +184,31 @@ async fn adds<'a>(args: &clap::ArgMatches<'a>) -> i32 {
         },
         bind_port: bindport,
         goto: gotols,
+        visit_key: args.value_of("visit-key").map(|v| v.to_string()),
+        health_check: if args.is_present("health") {
+            Some(true)
+        } else {
+            None
+        },
+        allow: args.values_of("allow").map(|vs| vs.map(|v| v.to_string()).collect()),
+        deny: args.values_of("deny").map(|vs| vs.map(|v| v.to_string()).collect()),
+        mode: args.value_of("mode").map(|v| v.to_string()),
+        routes: None,
+        balance: args.value_of("balance").map(|v| v.to_string()),
+        budget: Some(budget).filter(|v| v.up > 0 || v.down > 0),
+        max_conns: caps[0],
+        max_conns_per_ip: caps[1],
+        conn_rate: caps[2],
     };
-    let mut req = Application::new_reqs(3, "ProxyAdd");
-    match req.do_json(None, &data).await {
+    let bds = match serde_json::to_vec(&data) {
+        Err(e) => {
+            eprintln!("request json err:{}", e);
+            return -2;
+        }
+        Ok(v) => v,
+    };
+    let mut req = Application::new_reqs_with(3, "ProxyAdd", &[], Some(&bds[..]));
+    match req.dors(None, Some(bds.into_boxed_slice())).await {
         Err(e) => {
             eprintln!("request do err:{}", e);
             return -2;
@@ -166,20 +251,26 @@ async fn lss<'a>(_: &clap::ArgMatches<'a>) -> i32 {
                     Ok(v) => v,
                 };
                 println!(
-                    "{:<30}{:<20}{:<40}{:^10}{:<25}",
-                    "Name", "Bind", "Proxy", "Status", "Msg"
+                    "{:<30}{:<20}{:<40}{:^10}{:^10}{:<28}{:<25}",
+                    "Name", "Bind", "Proxy", "Status", "Health", "Traffic", "Msg"
                 );
                 for v in &data.list {
                     let msgs = match &v.msg {
                         None => "<nil>".to_string(),
                         Some(v) => v.clone(),
                     };
+                    let trfs = match &v.traffic {
+                        None => "-".to_string(),
+                        Some(v) => v.trafficstr(),
+                    };
                     println!(
-                        "{:<30}{:<20}{:<40}{:^10}{:<25}",
+                        "{:<30}{:<20}{:<40}{:^10}{:^10}{:<28}{:<25}",
                         v.name.as_str(),
-                        v.remote.as_str(),
+                        v.bindstr().as_str(),
                         v.proxystr().as_str(),
                         v.status,
+                        v.healthstr().as_str(),
+                        trfs.as_str(),
                         msgs.as_str()
                     );
                 }
@@ -203,8 +294,7 @@ async fn starts<'a>(args: &clap::ArgMatches<'a>) -> i32 {
         println!("name is required");
         return -1;
     };
-    let mut req = Application::new_reqs(3, "ProxyStart");
-    req.add_arg("name", names);
+    let mut req = Application::new_reqs_with(3, "ProxyStart", &[("name", names)], None);
     match req.dors(None, None).await {
         Err(e) => {
             eprintln!("request do err:{}", e);
@@ -236,8 +326,7 @@ async fn stops<'a>(args: &clap::ArgMatches<'a>) -> i32 {
         println!("name is required");
         return -1;
     };
-    let mut req = Application::new_reqs(3, "ProxyStop");
-    req.add_arg("name", names);
+    let mut req = Application::new_reqs_with(3, "ProxyStop", &[("name", names)], None);
     match req.dors(None, None).await {
         Err(e) => {
             eprintln!("request do err:{}", e);
@@ -269,8 +358,7 @@ async fn rms<'a>(args: &clap::ArgMatches<'a>) -> i32 {
         println!("name is required");
         return -1;
     };
-    let mut req = Application::new_reqs(3, "ProxyRemove");
-    req.add_arg("name", names);
+    let mut req = Application::new_reqs_with(3, "ProxyRemove", &[("name", names)], None);
     match req.dors(None, None).await {
         Err(e) => {
             eprintln!("request do err:{}", e);
@@ -295,3 +383,52 @@ async fn rms<'a>(args: &clap::ArgMatches<'a>) -> i32 {
     }
     0
 }
+async fn acls<'a>(args: &clap::ArgMatches<'a>) -> i32 {
+    let names = if let Some(vs) = args.value_of("name") {
+        vs
+    } else {
+        println!("name is required");
+        return -1;
+    };
+    let data = ProxyAclReq {
+        allow: match args.values_of("allow") {
+            None => Vec::new(),
+            Some(vs) => vs.map(|v| v.to_string()).collect(),
+        },
+        deny: match args.values_of("deny") {
+            None => Vec::new(),
+            Some(vs) => vs.map(|v| v.to_string()).collect(),
+        },
+    };
+    let bds = match serde_json::to_vec(&data) {
+        Err(e) => {
+            eprintln!("request json err:{}", e);
+            return -2;
+        }
+        Ok(v) => v,
+    };
+    let mut req = Application::new_reqs_with(3, "ProxyAcl", &[("name", names)], Some(&bds[..]));
+    match req.dors(None, Some(bds.into_boxed_slice())).await {
+        Err(e) => {
+            eprintln!("request do err:{}", e);
+            return -2;
+        }
+        Ok(res) => {
+            if res.get_code() == hbtp::ResCodeOk {
+                if let Some(bs) = res.get_bodys() {
+                    if let Ok(vs) = std::str::from_utf8(&bs[..]) {
+                        println!("acl:{}", vs);
+                    }
+                }
+            } else {
+                if let Some(bs) = res.get_bodys() {
+                    if let Ok(vs) = std::str::from_utf8(&bs[..]) {
+                        eprintln!("res err:{}", vs);
+                    }
+                }
+                return -3;
+            }
+        }
+    }
+    0
+}
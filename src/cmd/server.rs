@@ -1,8 +1,22 @@
-use std::io;
+use std::{
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 use async_std::task;
 
-use crate::{app::Application, engine::ServerCase, utils};
+use crate::{
+    app::Application,
+    engine::{
+        tls::{self, TlsFront},
+        run_metrics, ServerCase,
+    },
+    utils,
+};
+
+// the engines behind a TlsFront
+static NODE_TLS: AtomicBool = AtomicBool::new(false);
+static API_TLS: AtomicBool = AtomicBool::new(false);
 
 pub async fn runs<'a>(args: &clap::ArgMatches<'a>) -> i32 {
     if let Some(vs) = args.value_of("hosts") {
@@ -16,9 +30,40 @@ pub async fn runs<'a>(args: &clap::ArgMatches<'a>) -> i32 {
         }
     };
 
-    let addrs = Application::get().apiaddrs.clone();
-    let cs = ServerCase::new(Application::context());
+    let (apitls_cert, apitls_key) = match &Application::get().conf {
+        None => (None, None),
+        Some(v) => match &v.api_server {
+            None => (None, None),
+            Some(vc) => (vc.tls_cert.clone(), vc.tls_key.clone()),
+        },
+    };
+    let addrs = match serv_addrs(Application::get().apiaddrs.clone(), apitls_cert, apitls_key, &API_TLS) {
+        Err(e) => {
+            log::error!("server api tls err:{}", e);
+            return 1;
+        }
+        Ok(v) => v,
+    };
+    let cs = match ServerCase::new(Application::context()) {
+        Err(e) => {
+            log::error!("server init err:{}", e);
+            return 1;
+        }
+        Ok(v) => v,
+    };
     cs.start().await;
+    if let Some(host) = Application::get()
+        .conf
+        .as_ref()
+        .and_then(|v| v.server.metrics_host.clone())
+    {
+        let cs = cs.clone();
+        task::spawn(async move {
+            if let Err(e) = run_metrics(Application::context(), host, cs).await {
+                log::error!("metrics run err:{}", e);
+            }
+        });
+    }
     Application::get_mut().server_case = Some(cs);
     task::spawn(async move {
         let serv = hbtp::Engine::new(Some(Application::context()), addrs.as_str());
@@ -36,7 +81,17 @@ pub async fn runs<'a>(args: &clap::ArgMatches<'a>) -> i32 {
         }
         log::debug!("server api end!");
     });
-    let addrs = Application::get().addrs.clone();
+    let (tls_cert, tls_key) = match &Application::get().conf {
+        None => (None, None),
+        Some(v) => (v.server.tls_cert.clone(), v.server.tls_key.clone()),
+    };
+    let addrs = match serv_addrs(Application::get().addrs.clone(), tls_cert, tls_key, &NODE_TLS) {
+        Err(e) => {
+            log::error!("server tls err:{}", e);
+            return 1;
+        }
+        Ok(v) => v,
+    };
     let serv = hbtp::Engine::new(Some(Application::context()), addrs.as_str());
     serv.set_lmt_max(hbtp::LmtMaxConfig {
         max_ohther: 1024 * 10,  //10K
@@ -53,34 +108,76 @@ pub async fn runs<'a>(args: &clap::ArgMatches<'a>) -> i32 {
     0
 }
 
+// with tls,the hbtp engine listens on loopback and TlsFront serves addrs
+fn serv_addrs(
+    addrs: String,
+    cert: Option<String>,
+    key: Option<String>,
+    fronted: &AtomicBool,
+) -> io::Result<String> {
+    let (cert, key) = match (cert, key) {
+        (Some(c), Some(k)) => (c, k),
+        (None, None) => return Ok(addrs),
+        _ => return Err(ruisutil::ioerr("tls_cert and tls_key must be set together", None)),
+    };
+    let acceptor = tls::load_acceptor(cert.as_str(), key.as_str())?;
+    let locals = tls::local_addr()?;
+    fronted.store(true, Ordering::SeqCst);
+    let front = TlsFront::new(Application::context(), addrs.clone(), locals.clone(), acceptor);
+    task::spawn(async move {
+        log::info!("tls front start on:{}", addrs.as_str());
+        if let Err(e) = front.run().await {
+            log::error!("tls front run err:{}", e);
+        }
+    });
+    Ok(locals)
+}
+// a direct conn to an engine behind a front skips tls,TlsCheck is the front's own probe
+fn tls_refused(c: &hbtp::Context, fronted: &AtomicBool) -> bool {
+    fronted.load(Ordering::SeqCst) && c.command() != "TlsCheck" && !tls::fronted(c)
+}
+
 async fn handles(c: hbtp::Context) -> io::Result<()> {
+    if tls_refused(&c, &NODE_TLS) {
+        return Err(ruisutil::ioerr("conn is not through tls", None));
+    }
     let cs = match &Application::get().server_case {
         Some(v) => v,
         None => {
             return Err(ruisutil::ioerr("not init ok!!!", None));
         }
     };
-    if c.command() != "version" {
+    if c.command() != "version" && c.command() != "TlsCheck" {
         if let Some(vs) = cs.authed_server(&c) {
             return c.res_string(hbtp::ResCodeAuth, vs).await;
         }
     }
     match c.command() {
         "version" => c.res_string(hbtp::ResCodeOk, crate::app::VERSION).await,
+        "TlsCheck" => tls::check_res(c).await,
         "NodeJoin" => cs.node_reg(c).await,
         "NodeConn" => cs.node_conn(c).await,
         "NodeConns" => cs.node_conns(c).await,
+        "NodePark" => cs.node_park(c).await,
+        "NodeVisit" => cs.node_visit(c).await,
         _ => Err(ruisutil::ioerr("Not found Method", None)),
     }
 }
 
 async fn handle1(c: hbtp::Context) -> io::Result<()> {
+    if tls_refused(&c, &API_TLS) {
+        return Err(ruisutil::ioerr("conn is not through tls", None));
+    }
     match c.command() {
         "version" => c.res_string(hbtp::ResCodeOk, crate::app::VERSION).await,
+        "TlsCheck" => tls::check_res(c).await,
         _ => Err(ruisutil::ioerr("Not found Method", None)),
     }
 }
 async fn handle2(c: hbtp::Context) -> io::Result<()> {
+    if tls_refused(&c, &API_TLS) {
+        return Err(ruisutil::ioerr("conn is not through tls", None));
+    }
     let cs = match &Application::get().server_case {
         Some(v) => v,
         None => {
@@ -98,6 +195,9 @@ async fn handle2(c: hbtp::Context) -> io::Result<()> {
     }
 }
 async fn handle3(c: hbtp::Context) -> io::Result<()> {
+    if tls_refused(&c, &API_TLS) {
+        return Err(ruisutil::ioerr("conn is not through tls", None));
+    }
     let cs = match &Application::get().server_case {
         Some(v) => v,
         None => {
@@ -115,6 +215,11 @@ async fn handle3(c: hbtp::Context) -> io::Result<()> {
         "ProxyStop" => cs.proxy_stop(c).await,
         "ProxyRemove" => cs.proxy_remove(c).await,
         "ProxyReload" => cs.proxy_reload(c).await,
+        "ProxyAcl" => cs.proxy_acl(c).await,
+        "ConnList" => cs.conn_list(c).await,
+        "ConnKill" => cs.conn_kill(c).await,
+        "BudgetList" => cs.budget_list(c).await,
+        "BudgetSet" => cs.budget_set(c).await,
         _ => Err(ruisutil::ioerr("Not found Method", None)),
     }
 }
@@ -0,0 +1,194 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{BuildHasher, Hash, Hasher},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::entity::node::ProxyGoto;
+
+pub const BALANCES: [&str; 5] = ["failover", "round-robin", "random", "least-conn", "source-hash"];
+
+/// picks the goto a conn tries first,the others follow in config order as failover.
+pub struct Balancer {
+    strategy: String,
+    rr: AtomicUsize,
+    actives: Mutex<HashMap<String, usize>>,
+}
+
+pub(super) fn goto_key(v: &ProxyGoto) -> String {
+    format!(
+        "{}({}):{}",
+        v.proxy_host.as_str(),
+        v.localhost.as_deref().unwrap_or(""),
+        v.proxy_port
+    )
+}
+fn weights(gotos: &[ProxyGoto]) -> Vec<usize> {
+    let ws: Vec<usize> = gotos
+        .iter()
+        .map(|v| v.weight.unwrap_or(1) as usize)
+        .collect();
+    if ws.iter().all(|v| *v == 0) {
+        vec![1; ws.len()]
+    } else {
+        ws
+    }
+}
+// the goto whose weight range holds n
+fn weighted(ws: &[usize], n: usize) -> usize {
+    let total: usize = ws.iter().sum();
+    let mut n = n % total;
+    for (i, w) in ws.iter().enumerate() {
+        if n < *w {
+            return i;
+        }
+        n -= w;
+    }
+    0
+}
+
+impl Balancer {
+    pub fn new(strategy: &str) -> Self {
+        Self {
+            strategy: strategy.to_string(),
+            rr: AtomicUsize::new(0),
+            actives: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn order(&self, gotos: &[ProxyGoto], src: Option<IpAddr>) -> Vec<usize> {
+        let mut rts: Vec<usize> = (0..gotos.len()).collect();
+        if gotos.len() <= 1 {
+            return rts;
+        }
+        let ws = weights(gotos);
+        let first = match self.strategy.as_str() {
+            "round-robin" => weighted(&ws, self.rr.fetch_add(1, Ordering::SeqCst)),
+            "random" => {
+                let rs = std::collections::hash_map::RandomState::new();
+                weighted(&ws, rs.hash_one(self.rr.fetch_add(1, Ordering::SeqCst)) as usize)
+            }
+            "source-hash" => match src {
+                None => 0,
+                Some(ip) => {
+                    let mut h = DefaultHasher::new();
+                    ip.hash(&mut h);
+                    weighted(&ws, h.finish() as usize)
+                }
+            },
+            "least-conn" => {
+                let lkv = match self.actives.lock() {
+                    Err(_) => return rts,
+                    Ok(v) => v,
+                };
+                let mut first = 0;
+                let mut best: Option<(usize, usize)> = None;
+                for (i, v) in gotos.iter().enumerate() {
+                    if ws[i] == 0 {
+                        continue;
+                    }
+                    let n = lkv.get(&goto_key(v)).cloned().unwrap_or(0);
+                    // n/w < bn/bw
+                    match best {
+                        Some((bn, bw)) if n * bw >= bn * ws[i] => {}
+                        _ => {
+                            best = Some((n, ws[i]));
+                            first = i;
+                        }
+                    }
+                }
+                first
+            }
+            _ => 0,
+        };
+        rts.remove(first);
+        rts.insert(0, first);
+        rts
+    }
+
+    /// count the goto as active until the guard is dropped
+    pub fn active(self: &Arc<Self>, v: &ProxyGoto) -> ActiveGuard {
+        let key = goto_key(v);
+        if let Ok(mut lkv) = self.actives.lock() {
+            *lkv.entry(key.clone()).or_insert(0) += 1;
+        }
+        ActiveGuard {
+            blc: self.clone(),
+            key,
+        }
+    }
+}
+
+pub struct ActiveGuard {
+    blc: Arc<Balancer>,
+    key: String,
+}
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        if let Ok(mut lkv) = self.blc.actives.lock() {
+            if let Some(n) = lkv.get_mut(&self.key) {
+                *n -= 1;
+                if *n == 0 {
+                    lkv.remove(&self.key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::Balancer;
+    use crate::entity::node::ProxyGoto;
+
+    fn gotos(ws: &[u32]) -> Vec<ProxyGoto> {
+        ws.iter()
+            .enumerate()
+            .map(|(i, w)| ProxyGoto {
+                proxy_host: format!("n{}", i),
+                proxy_port: 80,
+                localhost: None,
+                limit: None,
+                weight: Some(*w),
+                proxy_protocol: None,
+                compress: None,
+                encrypt: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn balances() {
+        let gs = gotos(&[1, 2, 0]);
+        let blc = Balancer::new("failover");
+        assert_eq!(blc.order(&gs, None), vec![0, 1, 2]);
+
+        let blc = Balancer::new("round-robin");
+        let firsts: Vec<usize> = (0..6).map(|_| blc.order(&gs, None)[0]).collect();
+        assert_eq!(firsts, vec![0, 1, 1, 0, 1, 1]);
+        assert_eq!(blc.order(&gs, None), vec![0, 1, 2]);
+        assert_eq!(blc.order(&gs, None), vec![1, 0, 2]);
+
+        let blc = Arc::new(Balancer::new("least-conn"));
+        let g1 = blc.active(&gs[0]);
+        assert_eq!(blc.order(&gs, None)[0], 1);
+        let g2 = blc.active(&gs[1]);
+        assert_eq!(blc.order(&gs, None)[0], 1);
+        let g3 = blc.active(&gs[1]);
+        assert_eq!(blc.order(&gs, None)[0], 0);
+        drop((g1, g2, g3));
+        assert_eq!(blc.order(&gs, None)[0], 0);
+
+        let blc = Balancer::new("source-hash");
+        let ip = "10.0.0.7".parse().ok();
+        let first = blc.order(&gs, ip)[0];
+        assert!(first < 2);
+        assert_eq!(blc.order(&gs, ip)[0], first);
+    }
+}
@@ -1,12 +1,24 @@
 use std::{collections::LinkedList, io, time::Duration};
 
-use async_std::{net::TcpStream, sync::Mutex, task};
+use async_std::{
+    net::{TcpListener, TcpStream},
+    sync::Mutex,
+    task,
+};
 
 use crate::{
     app::Application,
-    engine::proxyer::{Proxyer, ProxyerCfg},
-    entity::node::{NodeConnMsg, RegNodeRep, RegNodeReq},
-    utils::{self, msg::Messages},
+    engine::{
+        conn::ProxyConn,
+        health,
+        mux::{self, MuxSession},
+        node::NODE_PARK_IDLE,
+        proxy_proto,
+        proxyer::{Proxyer, ProxyerCfg},
+        udper::{self, Udper},
+    },
+    entity::node::{NodeConnMsg, NodeVisitMsg, ProxyProtoMsg, RegNodeRep, RegNodeReq},
+    utils::{self, msg::Messages, Notify, NOTIFY_RECHECK},
 };
 
 #[derive(Clone)]
@@ -14,6 +26,17 @@ pub struct NodeClientCfg {
     pub name: String,
     pub token: Option<String>,
     pub remote_version: String,
+    pub mux: bool,
+    pub visits: Vec<NodeVisitCfg>,
+    pub visit_key: Option<String>,
+    // data conns parked on the server,0 is none
+    pub pool: usize,
+}
+/// a listener on this node whose conns go to a server rule
+#[derive(Clone)]
+pub struct NodeVisitCfg {
+    pub bind: String,
+    pub rule: String,
 }
 #[derive(Clone)]
 pub struct NodeClient {
@@ -27,6 +50,9 @@ struct Inner {
     ctms: ruisutil::Timer,
     ctmout: ruisutil::Timer,
     msgs: Mutex<LinkedList<Messages>>,
+    // fired on every queued msg,closed on stop
+    sends: Notify,
+    mux: Option<MuxSession>,
 
     connhost: String,
     isoldconn: bool,
@@ -38,14 +64,23 @@ impl NodeClient {
             utils::CompareVersion::Less | utils::CompareVersion::Eq => true,
             _ => false,
         };
+        let ctx = ruisutil::Context::background(Some(ctx));
+        let sends = Notify::new();
+        let mux = if cfg.mux {
+            Some(MuxSession::new(ctx.clone(), sends.clone()))
+        } else {
+            None
+        };
         Self {
             inner: ruisutil::ArcMut::new(Inner {
-                ctx: ruisutil::Context::background(Some(ctx)),
+                ctx,
                 cfg: cfg,
                 conn: conn,
                 ctms: ruisutil::Timer::new(Duration::from_secs(20)),
                 ctmout: ruisutil::Timer::new(Duration::from_secs(30)),
                 msgs: Mutex::new(LinkedList::new()),
+                sends,
+                mux,
 
                 connhost: utils::envs("HBPROXY_CLI2HOST", "localhost"),
                 isoldconn: isold,
@@ -54,6 +89,7 @@ impl NodeClient {
     }
     fn stop(&self) {
         self.inner.ctx.stop();
+        self.inner.sends.close();
         let ins = unsafe { self.inner.muts() };
         if let Err(e) = ins.conn.shutdown(std::net::Shutdown::Both) {
             log::error!("stop shutdown err:{}", e);
@@ -77,6 +113,27 @@ impl NodeClient {
             c.run_recv().await;
             println!("client run_recv end!!");
         });
+        if self.inner.cfg.pool > 0 {
+            if self.inner.mux.is_some() || !self.pool_since() {
+                log::warn!("server not support pool or mux is on,pool is off");
+            } else {
+                for _ in 0..self.inner.cfg.pool {
+                    let c = self.clone();
+                    task::spawn(async move {
+                        c.run_park().await;
+                    });
+                }
+            }
+        }
+        for v in &self.inner.cfg.visits {
+            let c = self.clone();
+            let v = v.clone();
+            task::spawn(async move {
+                if let Err(e) = c.run_visit(&v).await {
+                    log::error!("visit {} listen {} err:{}", v.rule.as_str(), v.bind.as_str(), e);
+                }
+            });
+        }
         log::debug!(
             "NodeClient run waits start:{}",
             self.inner.cfg.name.as_str()
@@ -103,6 +160,18 @@ impl NodeClient {
                     task::sleep(Duration::from_millis(100)).await;
                 }
                 Ok(v) => {
+                    if let Some(mux) = &self.inner.mux {
+                        match v.control {
+                            mux::MUX_CTRL_DATA
+                            | mux::MUX_CTRL_WINDOW
+                            | mux::MUX_CTRL_FIN
+                            | mux::MUX_CTRL_CLOSE => {
+                                mux.on_msg(v).await;
+                                continue;
+                            }
+                            _ => {}
+                        }
+                    }
                     let c = self.clone();
                     log::debug!("run_recv msg ctrl:{}", v.control);
                     task::spawn(async move {
@@ -115,22 +184,25 @@ impl NodeClient {
     async fn run_send(&self) {
         let ins = unsafe { self.inner.muts() };
         while !self.inner.ctx.done() {
-            let msg = {
+            let mut msg = {
                 let mut lkv = self.inner.msgs.lock().await;
                 lkv.pop_front()
             };
+            if msg.is_none() {
+                if let Some(mux) = &self.inner.mux {
+                    msg = mux.pop_msg().await;
+                }
+            }
             if let Some(v) = msg {
                 if let Err(e) = utils::msg::send_msgs(&self.inner.ctx, &mut ins.conn, v).await {
                     log::error!("run_send send_msgs err:{}", e);
                     /* if let Ok(mut lkv) = self.inner.waits.write() {
                         lkv.remove(&xids);
                     } */
-                } else {
-                    // log::debug!("run_send send_msgs ok:{}", ctrl);
-                    continue;
                 }
+                continue;
             }
-            task::sleep(Duration::from_millis(10)).await;
+            self.inner.sends.wait(NOTIFY_RECHECK).await;
         }
     }
 
@@ -143,13 +215,16 @@ impl NodeClient {
             self.stop();
         }
         if self.inner.ctms.tick() {
-            let mut lkv = self.inner.msgs.lock().await;
-            lkv.push_front(Messages {
-                control: 0,
-                cmds: Some("heart".into()),
-                heads: None,
-                bodys: None,
-            })
+            {
+                let mut lkv = self.inner.msgs.lock().await;
+                lkv.push_front(Messages {
+                    control: 0,
+                    cmds: Some("heart".into()),
+                    heads: None,
+                    bodys: None,
+                });
+            }
+            self.inner.sends.notify();
         }
     }
     async fn on_msg(&self, msg: utils::msg::Message) {
@@ -168,7 +243,19 @@ impl NodeClient {
 
                     let c = self.clone();
                     task::spawn(async move {
-                        c.task_conn(data).await;
+                        c.task_conn(data, None).await;
+                    });
+                }
+            }
+            health::NODE_CTRL_PROBE => {
+                if let Some(bds) = msg.bodys {
+                    let data: NodeConnMsg = match serde_json::from_slice(&bds) {
+                        Err(_) => return,
+                        Ok(v) => v,
+                    };
+                    let c = self.clone();
+                    task::spawn(async move {
+                        c.task_probe(data).await;
                     });
                 }
             }
@@ -176,12 +263,59 @@ impl NodeClient {
         }
     }
 
-    async fn task_conn(&self, data: NodeConnMsg) {
+    // dial the target and close it at once,the server only needs to know it is reachable
+    async fn task_probe(&self, data: NodeConnMsg) {
         let hosts = match &data.host {
             None => self.inner.connhost.as_str(),
             Some(v) => v.as_str(),
         };
         let addrs = format!("{}:{}", hosts, data.port);
+        let ok = match async_std::io::timeout(
+            health::PROBE_TIMEOUT / 2,
+            TcpStream::connect(addrs.as_str()),
+        )
+        .await
+        {
+            Ok(v) => {
+                let _ = v.shutdown(std::net::Shutdown::Both);
+                true
+            }
+            Err(e) => {
+                log::debug!("probe {} err:{}", addrs.as_str(), e);
+                false
+            }
+        };
+        {
+            let mut lkv = self.inner.msgs.lock().await;
+            lkv.push_back(Messages {
+                control: health::NODE_CTRL_PROBED,
+                cmds: Some(data.xids),
+                heads: Some(if ok { "ok" } else { "err" }.as_bytes().into()),
+                bodys: None,
+            });
+        }
+        self.inner.sends.notify();
+    }
+
+    /// parked: the parked conn the server sent data on,it becomes the tunnel
+    async fn task_conn(&self, data: NodeConnMsg, parked: Option<TcpStream>) {
+        let hosts = match &data.host {
+            None => self.inner.connhost.as_str(),
+            Some(v) => v.as_str(),
+        };
+        let addrs = format!("{}:{}", hosts, data.port);
+        let key = match seal_key(&data) {
+            Err(e) => {
+                log::error!("start_conn encrypt err:{}", e);
+                self.new_tunnel(&data, false, false, false, parked).await;
+                return;
+            }
+            Ok(v) => v,
+        };
+        if let Some("udp") = data.protocol.as_deref() {
+            self.task_conn_udp(data, addrs, key, parked).await;
+            return;
+        }
         let connlc = match TcpStream::connect(addrs.as_str()).await {
             Ok(v) => Some(v),
             Err(e) => {
@@ -189,74 +323,300 @@ impl NodeClient {
                 None
             }
         };
+        // the header goes first,before the tunnel is ok and the client bytes flow
+        let connlc = match (connlc, &data.proxy_proto) {
+            (Some(mut conn), Some(pp)) => match self.write_proxy_proto(&mut conn, pp).await {
+                Err(e) => {
+                    log::error!("start_conn proxy_protocol err:{}", e);
+                    None
+                }
+                Ok(_) => Some(conn),
+            },
+            (v, _) => v,
+        };
         log::debug!("client Proxyer start on -> {}", addrs.as_str());
-        if self.inner.isoldconn {
-            self.new_conn(data, addrs, connlc).await;
-        } else {
-            self.new_conns(data, addrs, connlc).await;
+        let compress = data.compress == Some(true);
+        let connok = connlc.is_some();
+        let encrypt = key.is_some();
+        if let Some(conn) = self.new_tunnel(&data, connok, compress, encrypt, parked).await {
+            let conn = match &key {
+                Some(key) => conn.sealed(key.as_str(), data.xids.as_str(), false),
+                None => conn,
+            };
+            let conn = if compress { conn.deflated() } else { conn };
+            if let Some(connlc) = connlc {
+                let px = Proxyer::new(
+                    self.inner.ctx.clone(),
+                    ProxyerCfg {
+                        ids: addrs,
+                        limit: None,
+                        stats: Vec::new(),
+                        budgets: Vec::new(),
+                    },
+                    conn,
+                    ProxyConn::Tcp(connlc),
+                );
+                px.start().await;
+            }
         }
     }
-    async fn new_conn(&self, data: NodeConnMsg, addrs: String, conn: Option<TcpStream>) {
-        // log::debug!("start new_conn -> :{}",data.port);
-        let mut req = Application::new_req(1, "NodeConn", false);
-        if let None = conn {
-            req.add_arg("err", "1");
+    fn pool_since(&self) -> bool {
+        matches!(
+            utils::compare_version(&self.inner.cfg.remote_version, "0.4.0".into()),
+            utils::CompareVersion::Great | utils::CompareVersion::Eq
+        )
+    }
+    /// one slot of the pool: park a conn,wait for the server to claim it,park the next
+    async fn run_park(&self) {
+        while !self.inner.ctx.done() {
+            match self.park().await {
+                // idle out,park the next at once
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {}
+                Err(e) => {
+                    log::debug!("park conn err:{}", e);
+                    task::sleep(Duration::from_secs(2)).await;
+                }
+                Ok((conn, data)) => {
+                    log::debug!("parked conn claimed:{}", data.xids.as_str());
+                    let c = self.clone();
+                    task::spawn(async move {
+                        c.task_conn(data, Some(conn)).await;
+                    });
+                }
+            }
         }
-        match req.do_json(None, &data).await {
+    }
+    async fn park(&self) -> io::Result<(TcpStream, NodeConnMsg)> {
+        let token = self.inner.cfg.token.as_deref().unwrap_or_default();
+        let args = [("name", self.inner.cfg.name.as_str()), ("token", token)];
+        let mut req = Application::new_req_with(1, "NodePark", false, &args[..], None);
+        let mut res = req.dors(None, None).await?;
+        if res.get_code() != hbtp::ResCodeOk {
+            let errs = match res.get_bodys() {
+                None => String::new(),
+                Some(bs) => String::from_utf8_lossy(&bs[..]).to_string(),
+            };
+            return Err(ruisutil::ioerr(format!("park res err:{}", errs), None));
+        }
+        let mut conn = res.own_conn();
+        // the server drops it once idle,park a new one then
+        let msg = utils::msg::parse_msg(&self.inner.ctx, &mut conn);
+        let msg = async_std::io::timeout(NODE_PARK_IDLE, msg).await?;
+        let data: NodeConnMsg = match msg.bodys.map(|v| serde_json::from_slice(&v)) {
+            Some(Ok(v)) if msg.control == 1 => v,
+            _ => return Err(ruisutil::ioerr("parked conn msg err", None)),
+        };
+        Ok((conn, data))
+    }
+    async fn write_proxy_proto(&self, conn: &mut TcpStream, pp: &ProxyProtoMsg) -> io::Result<()> {
+        let bts = proxy_proto::header(pp)?;
+        ruisutil::tcp_write_async(&self.inner.ctx, conn, &bts).await
+    }
+    async fn task_conn_udp(
+        &self,
+        data: NodeConnMsg,
+        addrs: String,
+        key: Option<String>,
+        parked: Option<TcpStream>,
+    ) {
+        let connlc = match udper::connect(addrs.as_str()).await {
+            Ok(v) => Some(v),
             Err(e) => {
-                log::error!("new_conn request do err:{}", e);
+                log::error!("start_conn Udper err:{}", e);
+                None
             }
-            Ok(res) => {
-                if let Some(connlc) = conn {
-                    self.start_conn(res, addrs, connlc).await;
-                }
+        };
+        log::debug!("client Udper start on -> {}", addrs.as_str());
+        let (connok, encrypt) = (connlc.is_some(), key.is_some());
+        if let Some(conn) = self.new_tunnel(&data, connok, false, encrypt, parked).await {
+            let conn = match &key {
+                Some(key) => conn.sealed(key.as_str(), data.xids.as_str(), false),
+                None => conn,
+            };
+            if let Some(connlc) = connlc {
+                let ud = Udper::new(self.inner.ctx.clone(), addrs, conn, connlc);
+                ud.start().await;
             }
         }
     }
-    async fn new_conns(&self, data: NodeConnMsg, addrs: String, conn: Option<TcpStream>) {
-        log::debug!("start new_conns -> :{}", data.port);
-        let mut req = Application::new_req(1, "NodeConns", false);
-        req.add_arg("name", data.name.as_str());
-        req.add_arg("xid", data.xids.as_str());
-        if let None = conn {
-            req.add_arg("err", "1");
+    async fn run_visit(&self, cfg: &NodeVisitCfg) -> io::Result<()> {
+        let lsr = TcpListener::bind(cfg.bind.as_str()).await?;
+        log::info!("visit {} listen on:{}", cfg.rule.as_str(), cfg.bind.as_str());
+        while !self.inner.ctx.done() {
+            let conn = match async_std::io::timeout(Duration::from_secs(1), lsr.accept()).await {
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+                Ok((v, _)) => v,
+            };
+            let c = self.clone();
+            let rule = cfg.rule.clone();
+            task::spawn(async move {
+                c.task_visit(conn, rule).await;
+            });
         }
-        match req.dors(None, None).await {
-            Err(e) => {
-                log::error!("new_conn request do err:{}", e);
+        Ok(())
+    }
+    async fn task_visit(&self, conn: TcpStream, rule: String) {
+        let data = NodeVisitMsg {
+            rule: rule.clone(),
+            key: self.inner.cfg.visit_key.clone(),
+        };
+        let connrm = if let Some(mux) = &self.inner.mux {
+            let bds = match serde_json::to_vec(&data) {
+                Err(e) => {
+                    log::error!("task_visit json err:{}", e);
+                    return;
+                }
+                Ok(v) => v,
+            };
+            let xids = format!("v{}", xid::new().to_string().as_str());
+            let stream = mux.open(&xids).await;
+            mux.send_visit(&xids, bds.into_boxed_slice()).await;
+            ProxyConn::Mux(stream)
+        } else {
+            let mut args = vec![("rule", data.rule.as_str())];
+            if let Some(vs) = &data.key {
+                args.push(("visit_key", vs.as_str()));
             }
-            Ok(res) => {
-                if let Some(connlc) = conn {
-                    self.start_conn(res, addrs, connlc).await;
+            let mut req = Application::new_req_with(1, "NodeVisit", false, &args[..], None);
+            match req.dors(None, None).await {
+                Err(e) => {
+                    log::error!("task_visit request do err:{}", e);
+                    return;
+                }
+                Ok(mut res) => {
+                    if res.get_code() != hbtp::ResCodeOk {
+                        if let Some(bs) = res.get_bodys() {
+                            if let Ok(vs) = std::str::from_utf8(&bs[..]) {
+                                log::error!("visit {} err:{}", rule.as_str(), vs);
+                            }
+                        }
+                        return;
+                    }
+                    ProxyConn::Tcp(res.own_conn())
                 }
             }
-        }
+        };
+        log::debug!("client visit start -> {}", rule.as_str());
+        let px = Proxyer::new(
+            self.inner.ctx.clone(),
+            ProxyerCfg {
+                ids: format!("visit:{}", rule.as_str()),
+                limit: None,
+                stats: Vec::new(),
+                budgets: Vec::new(),
+            },
+            ProxyConn::Tcp(conn),
+            connrm,
+        );
+        px.start().await;
     }
-    async fn start_conn(&self, mut res: hbtp::Response, addrs: String, connlc: TcpStream) {
+    /// compress,encrypt: tell the server this end deflates or seals the tunnel
+    async fn new_tunnel(
+        &self,
+        data: &NodeConnMsg,
+        connok: bool,
+        compress: bool,
+        encrypt: bool,
+        parked: Option<TcpStream>,
+    ) -> Option<ProxyConn> {
+        if let Some(mut conn) = parked {
+            let heads = mux::conn_heads(connok, compress, encrypt).into_bytes();
+            let ctrl = mux::MUX_CTRL_CONN;
+            let xids = Some(data.xids.clone());
+            let heads = Some(heads.into_boxed_slice());
+            let ctx = &self.inner.ctx;
+            if let Err(e) = utils::msg::send_msg(ctx, &mut conn, ctrl, xids, heads, None).await {
+                log::error!("parked conn answer err:{}", e);
+                return None;
+            }
+            return if connok { Some(ProxyConn::Tcp(conn)) } else { None };
+        }
+        if let Some(mux) = &self.inner.mux {
+            let conn = if connok {
+                Some(ProxyConn::Mux(mux.open(&data.xids).await))
+            } else {
+                None
+            };
+            mux.send_conn(&data.xids, connok, compress, encrypt).await;
+            return conn;
+        }
+        let res = if self.inner.isoldconn {
+            self.new_conn(data, connok, compress, encrypt).await
+        } else {
+            self.new_conns(data, connok, compress, encrypt).await
+        };
+        let mut res = res?;
         if res.get_code() == hbtp::ResCodeOk {
-            /* if let Some(bs) = res.get_bodys() {
-                if let Ok(vs) = std::str::from_utf8(&bs[..]) {
-                    log::debug!("start_conn ok:{}", vs);
-                }
-            } */
-
-            let px = Proxyer::new(
-                self.inner.ctx.clone(),
-                ProxyerCfg {
-                    ids: addrs,
-                    limit: None,
-                },
-                res.own_conn(),
-                connlc,
-            );
-            // let px = Proxyer::new(self.inner.ctx.clone(), res.own_conn(), data.port);
-            px.start().await;
+            Some(ProxyConn::Tcp(res.own_conn()))
         } else {
             if let Some(bs) = res.get_bodys() {
                 if let Ok(vs) = std::str::from_utf8(&bs[..]) {
                     log::error!("response err:{}", vs);
                 }
             }
+            None
+        }
+    }
+    async fn new_conn(
+        &self,
+        data: &NodeConnMsg,
+        connok: bool,
+        compress: bool,
+        encrypt: bool,
+    ) -> Option<hbtp::Response> {
+        // log::debug!("start new_conn -> :{}",data.port);
+        let bds = match serde_json::to_vec(data) {
+            Err(e) => {
+                log::error!("new_conn json err:{}", e);
+                return None;
+            }
+            Ok(v) => v,
+        };
+        let mut args = Vec::new();
+        if !connok {
+            args.push(("err", "1"));
+        }
+        if compress {
+            args.push(("compress", "1"));
+        }
+        if encrypt {
+            args.push(("encrypt", "1"));
+        }
+        let mut req = Application::new_req_with(1, "NodeConn", false, &args[..], Some(&bds[..]));
+        match req.dors(None, Some(bds.into_boxed_slice())).await {
+            Err(e) => {
+                log::error!("new_conn request do err:{}", e);
+                None
+            }
+            Ok(res) => Some(res),
+        }
+    }
+    async fn new_conns(
+        &self,
+        data: &NodeConnMsg,
+        connok: bool,
+        compress: bool,
+        encrypt: bool,
+    ) -> Option<hbtp::Response> {
+        log::debug!("start new_conns -> :{}", data.port);
+        let mut args = vec![("name", data.name.as_str()), ("xid", data.xids.as_str())];
+        if !connok {
+            args.push(("err", "1"));
+        }
+        if compress {
+            args.push(("compress", "1"));
+        }
+        if encrypt {
+            args.push(("encrypt", "1"));
+        }
+        let mut req = Application::new_req_with(1, "NodeConns", false, &args[..], None);
+        match req.dors(None, None).await {
+            Err(e) => {
+                log::error!("new_conn request do err:{}", e);
+                None
+            }
+            Ok(res) => Some(res),
         }
     }
 
@@ -277,6 +637,10 @@ impl NodeClient {
                 let mut cfgs = cfg.clone();
                 cfgs.token = Some(data.token.clone());
                 cfgs.remote_version = vers;
+                cfgs.mux = cfg.mux && data.mux.unwrap_or(false);
+                if cfg.mux && !cfgs.mux {
+                    log::warn!("server not support mux,use conns mode");
+                }
                 // conns = Some(conn);
                 let cli = Self::new(Application::context(), cfgs, conn);
                 if let Err(e) = cli.run().await {
@@ -306,13 +670,19 @@ impl NodeClient {
     }
     async fn connect(cfg: &NodeClientCfg) -> io::Result<(TcpStream, RegNodeRep)> {
         log::debug!("NodeClient connect start:{}", cfg.name.as_str());
-        let mut req = Application::new_req(1, "NodeJoin", false);
         let data = RegNodeReq {
             name: cfg.name.clone(),
             token: cfg.token.clone(),
             version: Some(crate::app::VERSION.into()),
+            mux: Some(cfg.mux),
+            probe: Some(true),
+        };
+        let bds = match serde_json::to_vec(&data) {
+            Err(e) => return Err(ruisutil::ioerr(e, None)),
+            Ok(v) => v,
         };
-        match req.do_json(None, &data).await {
+        let mut req = Application::new_req_with(1, "NodeJoin", false, &[], Some(&bds[..]));
+        match req.dors(None, Some(bds.into_boxed_slice())).await {
             Err(e) => {
                 log::error!("conntion request do err:{}", e);
                 task::sleep(Duration::from_secs(5)).await;
@@ -357,3 +727,14 @@ impl NodeClient {
         Err(ruisutil::ioerr("conn end err", None))
     }
 }
+
+/// the node key when the server asks for a sealed tunnel,an err when there is none
+fn seal_key(data: &NodeConnMsg) -> io::Result<Option<String>> {
+    if data.encrypt != Some(true) {
+        return Ok(None);
+    }
+    match &Application::get().keys {
+        Some(v) if !v.is_empty() => Ok(Some(v.clone())),
+        _ => Err(ruisutil::ioerr("no node key to seal with", None)),
+    }
+}
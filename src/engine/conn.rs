@@ -0,0 +1,80 @@
+use std::{io, net::Shutdown};
+
+use async_std::net::TcpStream;
+use futures::AsyncReadExt;
+
+use super::{deflate::DeflateConn, mux::MuxStream, seal::SealConn};
+
+/// a proxied conn: a plain tcp conn or a logical stream on the node control conn,
+/// a node tunnel may be sealed and deflated on top of either.
+pub enum ProxyConn {
+    Tcp(TcpStream),
+    Mux(MuxStream),
+    Deflate(Box<DeflateConn>),
+    Seal(Box<SealConn>),
+}
+
+impl ProxyConn {
+    /// server: this is the server end of the tunnel
+    pub fn sealed(self, secret: &str, xids: &str, server: bool) -> Self {
+        ProxyConn::Seal(Box::new(SealConn::new(self, secret, xids, server)))
+    }
+    /// deflate over a sealed conn,sealed bytes don't deflate
+    pub fn deflated(self) -> Self {
+        ProxyConn::Deflate(Box::new(DeflateConn::new(self)))
+    }
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ProxyConn::Tcp(conn) => conn.read(buf).await,
+            ProxyConn::Mux(conn) => conn.read(buf).await,
+            // boxed,a deflate or seal conn reads its inner ProxyConn
+            ProxyConn::Deflate(conn) => Box::pin(conn.read(buf)).await,
+            ProxyConn::Seal(conn) => Box::pin(conn.read(buf)).await,
+        }
+    }
+    pub async fn read_exact(&mut self, ctx: &ruisutil::Context, n: usize) -> io::Result<Box<[u8]>> {
+        match self {
+            ProxyConn::Tcp(conn) => ruisutil::tcp_read_async(ctx, conn, n).await,
+            conn => {
+                let mut buf = vec![0u8; n].into_boxed_slice();
+                let mut pos = 0;
+                while pos < n {
+                    if ctx.done() {
+                        return Err(ruisutil::ioerr("ctx end", None));
+                    }
+                    let ln = conn.read(&mut buf[pos..]).await?;
+                    if ln == 0 {
+                        return Err(ruisutil::ioerr("read size=0", None));
+                    }
+                    pos += ln;
+                }
+                Ok(buf)
+            }
+        }
+    }
+    pub async fn write_all(&mut self, ctx: &ruisutil::Context, bts: &[u8]) -> io::Result<()> {
+        match self {
+            ProxyConn::Tcp(conn) => ruisutil::tcp_write_async(ctx, conn, bts).await,
+            ProxyConn::Mux(conn) => conn.write_all(bts).await,
+            ProxyConn::Deflate(conn) => Box::pin(conn.write_all(ctx, bts)).await,
+            ProxyConn::Seal(conn) => Box::pin(conn.write_all(ctx, bts)).await,
+        }
+    }
+    /// the client addr of a tcp conn,a mux stream has none
+    pub fn peer_addr(&self) -> Option<String> {
+        match self {
+            ProxyConn::Tcp(conn) => conn.peer_addr().ok().map(|v| v.to_string()),
+            ProxyConn::Mux(_) => None,
+            ProxyConn::Deflate(conn) => conn.peer_addr(),
+            ProxyConn::Seal(conn) => conn.peer_addr(),
+        }
+    }
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            ProxyConn::Tcp(conn) => conn.shutdown(how),
+            ProxyConn::Mux(conn) => conn.shutdown(how),
+            ProxyConn::Deflate(conn) => conn.shutdown(how),
+            ProxyConn::Seal(conn) => conn.shutdown(how),
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use crate::entity::proxy::ConnListIt;
+
+use super::{proxyer::Proxyer, stats::TrafficStat};
+
+/// the proxyers being relayed by id,a conn is listed from its start to its end.
+pub struct ConnRegistry {
+    items: Mutex<HashMap<String, ConnItem>>,
+}
+struct ConnItem {
+    rule: Option<String>,
+    goto: String,
+    client: Option<String>,
+    start: SystemTime,
+    traffic: Arc<TrafficStat>,
+    px: Proxyer,
+}
+
+impl ConnRegistry {
+    pub fn new() -> Self {
+        Self {
+            items: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn add(
+        &self,
+        px: &Proxyer,
+        rule: Option<&str>,
+        goto: String,
+        client: Option<String>,
+        traffic: Arc<TrafficStat>,
+    ) -> String {
+        let id = xid::new().to_string();
+        if let Ok(mut lkv) = self.items.lock() {
+            lkv.insert(
+                id.clone(),
+                ConnItem {
+                    rule: rule.map(|v| v.to_string()),
+                    goto,
+                    client,
+                    start: SystemTime::now(),
+                    traffic,
+                    px: px.clone(),
+                },
+            );
+        }
+        id
+    }
+    pub fn remove(&self, id: &str) {
+        if let Ok(mut lkv) = self.items.lock() {
+            lkv.remove(id);
+        }
+    }
+
+    /// conns of a rule or all,the oldest first
+    pub fn list(&self, rule: Option<&str>) -> Vec<ConnListIt> {
+        let mut rts = Vec::new();
+        let lkv = match self.items.lock() {
+            Err(_) => return rts,
+            Ok(v) => v,
+        };
+        let mut ls: Vec<(&String, &ConnItem)> = lkv
+            .iter()
+            .filter(|(_, v)| rule.is_none() || v.rule.as_deref() == rule)
+            .collect();
+        ls.sort_by_key(|(_, v)| v.start);
+        for (k, v) in ls {
+            let info = v.traffic.info();
+            rts.push(ConnListIt {
+                id: k.clone(),
+                rule: v.rule.clone(),
+                goto: v.goto.clone(),
+                client: v.client.clone(),
+                times: match SystemTime::now().duration_since(v.start) {
+                    Err(_) => 0,
+                    Ok(v) => v.as_secs(),
+                },
+                up: info.up,
+                down: info.down,
+            });
+        }
+        rts
+    }
+
+    /// close both sides of a conn,false if the id is not found
+    pub fn kill(&self, id: &str) -> bool {
+        let px = match self.items.lock() {
+            Err(_) => return false,
+            Ok(lkv) => match lkv.get(id) {
+                None => return false,
+                Some(v) => v.px.clone(),
+            },
+        };
+        log::info!("conn {} is killed", id);
+        px.stop();
+        true
+    }
+}
@@ -0,0 +1,159 @@
+use std::{io, net::Shutdown};
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+
+use super::conn::ProxyConn;
+
+/// a raw deflate stream,every write is sync flushed so nothing waits for more bytes
+struct Deflater {
+    c: Compress,
+}
+impl Deflater {
+    fn new() -> Self {
+        Self {
+            c: Compress::new(Compression::fast(), false),
+        }
+    }
+    fn pack(&mut self, bts: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(bts.len() + 64);
+        let mut pos = 0;
+        loop {
+            let tin = self.c.total_in();
+            if let Err(e) = self.c.compress_vec(&bts[pos..], &mut out, FlushCompress::Sync) {
+                return Err(ruisutil::ioerr(format!("deflate err:{}", e), None));
+            }
+            pos += (self.c.total_in() - tin) as usize;
+            // the flush is all out once it leaves room
+            if pos >= bts.len() && out.len() < out.capacity() {
+                return Ok(out);
+            }
+            out.reserve(out.capacity().max(1024));
+        }
+    }
+}
+
+struct Inflater {
+    d: Decompress,
+    ins: Box<[u8]>,
+    pos: usize,
+    end: usize,
+}
+impl Inflater {
+    fn new() -> Self {
+        Self {
+            d: Decompress::new(false),
+            ins: vec![0u8; 10240].into_boxed_slice(),
+            pos: 0,
+            end: 0,
+        }
+    }
+    /// the room for more read bytes
+    fn space(&mut self) -> &mut [u8] {
+        if self.pos > 0 {
+            self.ins.copy_within(self.pos..self.end, 0);
+            self.end -= self.pos;
+            self.pos = 0;
+        }
+        &mut self.ins[self.end..]
+    }
+    fn filled(&mut self, n: usize) {
+        self.end += n;
+    }
+    /// 0 when it needs more bytes,it may hold output back with no bytes left
+    fn unpack(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let (tin, tout) = (self.d.total_in(), self.d.total_out());
+            let ins = &self.ins[self.pos..self.end];
+            if let Err(e) = self.d.decompress(ins, out, FlushDecompress::None) {
+                return Err(ruisutil::ioerr(format!("inflate err:{}", e), None));
+            }
+            let n = (self.d.total_in() - tin) as usize;
+            self.pos += n;
+            let ln = (self.d.total_out() - tout) as usize;
+            if ln > 0 || n == 0 {
+                return Ok(ln);
+            }
+        }
+    }
+}
+
+/// a node tunnel both ends agreed to deflate
+pub struct DeflateConn {
+    conn: ProxyConn,
+    enc: Deflater,
+    dec: Inflater,
+}
+impl DeflateConn {
+    pub fn new(conn: ProxyConn) -> Self {
+        Self {
+            conn,
+            enc: Deflater::new(),
+            dec: Inflater::new(),
+        }
+    }
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.dec.unpack(buf)?;
+            if n > 0 {
+                return Ok(n);
+            }
+            let space = self.dec.space();
+            if space.is_empty() {
+                return Err(ruisutil::ioerr("inflate buf full", None));
+            }
+            let ln = self.conn.read(space).await?;
+            if ln == 0 {
+                return Ok(0);
+            }
+            self.dec.filled(ln);
+        }
+    }
+    pub async fn write_all(&mut self, ctx: &ruisutil::Context, bts: &[u8]) -> io::Result<()> {
+        let bts = self.enc.pack(bts)?;
+        self.conn.write_all(ctx, &bts).await
+    }
+    pub fn peer_addr(&self) -> Option<String> {
+        self.conn.peer_addr()
+    }
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.conn.shutdown(how)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Deflater, Inflater};
+
+    // feed the packed bytes in chunks of step,what comes out
+    fn unpacks(dec: &mut Inflater, bts: &[u8], step: usize) -> Vec<u8> {
+        let mut rts = Vec::new();
+        let mut out = [0u8; 100];
+        for v in bts.chunks(step) {
+            let space = dec.space();
+            space[..v.len()].copy_from_slice(v);
+            dec.filled(v.len());
+            loop {
+                let n = dec.unpack(&mut out).unwrap();
+                if n == 0 {
+                    break;
+                }
+                rts.extend_from_slice(&out[..n]);
+            }
+        }
+        rts
+    }
+
+    #[test]
+    fn deflates() {
+        let (mut enc, mut dec) = (Deflater::new(), Inflater::new());
+        // each write comes out whole on its own,no waiting for the next
+        for msg in [&b"ls -la\n"[..], b"", b"x", &[b'a'; 50000][..]] {
+            let bts = enc.pack(msg).unwrap();
+            assert_eq!(unpacks(&mut dec, &bts, 1000), msg);
+        }
+        let txt = "GET / HTTP/1.1\r\nHost: example.com\r\n\r\n".repeat(100);
+        let bts = enc.pack(txt.as_bytes()).unwrap();
+        assert!(bts.len() < txt.len() / 10);
+        assert_eq!(unpacks(&mut dec, &bts, 1), txt.as_bytes());
+    }
+}
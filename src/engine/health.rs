@@ -0,0 +1,160 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::entity::{node::ProxyGoto, proxy::ProxyGotoHealth};
+
+use super::balance::goto_key;
+
+pub const NODE_CTRL_PROBE: i32 = 7; // server->node: dial a target,bodys:NodeConnMsg
+pub const NODE_CTRL_PROBED: i32 = 8; // node->server: probe result,cmds:xids,heads:"ok"/"err"
+
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const HEALTH_INTERVAL: Duration = Duration::from_secs(10);
+const HEALTH_BACKOFF_MAX: Duration = Duration::from_secs(300);
+
+/// probe results of the gotos of a rule,a down goto is skipped until a probe passes again.
+pub struct Health {
+    items: Mutex<HashMap<String, HealthItem>>,
+}
+struct HealthItem {
+    // None: the node can't probe
+    up: Option<bool>,
+    fails: u32,
+    next: Instant,
+    msg: Option<String>,
+}
+
+// wait before the next probe,doubled by each fail in a row
+fn backoff(fails: u32) -> Duration {
+    if fails <= 1 {
+        return HEALTH_INTERVAL;
+    }
+    let n = HEALTH_INTERVAL.saturating_mul(1 << (fails - 1).min(16));
+    n.min(HEALTH_BACKOFF_MAX)
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            items: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn due(&self, v: &ProxyGoto) -> bool {
+        match self.items.lock() {
+            Err(_) => false,
+            Ok(lkv) => match lkv.get(&goto_key(v)) {
+                None => true,
+                Some(it) => Instant::now() >= it.next,
+            },
+        }
+    }
+    pub fn is_down(&self, v: &ProxyGoto) -> bool {
+        match self.items.lock() {
+            Err(_) => false,
+            Ok(lkv) => match lkv.get(&goto_key(v)) {
+                None => false,
+                Some(it) => it.up == Some(false),
+            },
+        }
+    }
+
+    pub fn report(&self, v: &ProxyGoto, rt: io::Result<()>) {
+        let mut lkv = match self.items.lock() {
+            Err(_) => return,
+            Ok(v) => v,
+        };
+        let it = lkv.entry(goto_key(v)).or_insert(HealthItem {
+            up: None,
+            fails: 0,
+            next: Instant::now(),
+            msg: None,
+        });
+        match rt {
+            Ok(_) => {
+                if it.up == Some(false) {
+                    log::info!("goto {} is up", goto_key(v).as_str());
+                }
+                it.up = Some(true);
+                it.fails = 0;
+                it.next = Instant::now() + HEALTH_INTERVAL;
+                it.msg = None;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                it.up = None;
+                it.fails = 0;
+                it.next = Instant::now() + HEALTH_BACKOFF_MAX;
+                it.msg = Some(e.to_string());
+            }
+            Err(e) => {
+                if it.up != Some(false) {
+                    log::info!("goto {} is down:{}", goto_key(v).as_str(), e);
+                }
+                it.up = Some(false);
+                it.fails += 1;
+                it.next = Instant::now() + backoff(it.fails);
+                it.msg = Some(e.to_string());
+            }
+        }
+    }
+
+    pub fn list(&self, gotos: &[&ProxyGoto]) -> Vec<ProxyGotoHealth> {
+        let mut rts = Vec::new();
+        let lkv = match self.items.lock() {
+            Err(_) => return rts,
+            Ok(v) => v,
+        };
+        for v in gotos {
+            let key = goto_key(v);
+            if let Some(it) = lkv.get(&key) {
+                rts.push(ProxyGotoHealth {
+                    goto: key,
+                    up: it.up,
+                    fails: it.fails,
+                    msg: it.msg.clone(),
+                });
+            }
+        }
+        rts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, time::Duration};
+
+    use super::{backoff, Health};
+    use crate::entity::node::ProxyGoto;
+
+    #[test]
+    fn healths() {
+        assert_eq!(backoff(1), Duration::from_secs(10));
+        assert_eq!(backoff(3), Duration::from_secs(40));
+        assert_eq!(backoff(20), Duration::from_secs(300));
+
+        let v = ProxyGoto {
+            proxy_host: "n1".to_string(),
+            proxy_port: 80,
+            localhost: None,
+            limit: None,
+            weight: None,
+            proxy_protocol: None,
+            compress: None,
+            encrypt: None,
+        };
+        let hlt = Health::new();
+        assert!(hlt.due(&v));
+        assert!(!hlt.is_down(&v));
+        hlt.report(&v, Err(io::Error::new(io::ErrorKind::ConnectionRefused, "refused")));
+        assert!(hlt.is_down(&v));
+        assert!(!hlt.due(&v));
+        assert_eq!(hlt.list(&[&v])[0].fails, 1);
+        hlt.report(&v, Ok(()));
+        assert!(!hlt.is_down(&v));
+        assert_eq!(hlt.list(&[&v])[0].up, Some(true));
+    }
+}
@@ -0,0 +1,310 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_std::task;
+
+use crate::entity::util::ProxyLimit;
+
+/// a token bucket in bytes,rate 0 is no limit.
+/// a take goes below zero and the taker waits the debt out,so conns sharing it get turns.
+pub struct Bucket {
+    st: Mutex<BucketState>,
+}
+struct BucketState {
+    rate: u64,
+    burst: u64,
+    tokens: f64,
+    last: Instant,
+}
+impl Bucket {
+    pub fn new(rate: u64, burst: u64) -> Self {
+        Self {
+            st: Mutex::new(BucketState {
+                rate,
+                burst,
+                tokens: burst as f64,
+                last: Instant::now(),
+            }),
+        }
+    }
+    pub fn set(&self, rate: u64, burst: u64) {
+        if let Ok(mut st) = self.st.lock() {
+            st.rate = rate;
+            st.burst = burst;
+            st.tokens = st.tokens.min(burst as f64);
+        }
+    }
+
+    /// take n bytes,the wait before they may be sent
+    pub fn take(&self, n: usize) -> Duration {
+        self.take_at(n, Instant::now())
+    }
+    /// take n only if they are there,no debt
+    pub fn try_take(&self, n: usize) -> bool {
+        self.try_take_at(n, Instant::now())
+    }
+    fn try_take_at(&self, n: usize, now: Instant) -> bool {
+        let mut st = match self.st.lock() {
+            Err(_) => return true,
+            Ok(v) => v,
+        };
+        if st.rate == 0 {
+            return true;
+        }
+        let secs = now.saturating_duration_since(st.last).as_secs_f64();
+        st.last = now;
+        st.tokens = (st.tokens + secs * st.rate as f64).min(st.burst as f64);
+        if st.tokens < n as f64 {
+            return false;
+        }
+        st.tokens -= n as f64;
+        true
+    }
+    fn take_at(&self, n: usize, now: Instant) -> Duration {
+        let mut st = match self.st.lock() {
+            Err(_) => return Duration::ZERO,
+            Ok(v) => v,
+        };
+        if st.rate == 0 {
+            return Duration::ZERO;
+        }
+        let secs = now.saturating_duration_since(st.last).as_secs_f64();
+        st.last = now;
+        st.tokens = (st.tokens + secs * st.rate as f64).min(st.burst as f64);
+        st.tokens -= n as f64;
+        if st.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-st.tokens / st.rate as f64)
+    }
+}
+
+/// the bytes/s and burst of a kb/s rate
+fn rate_burst(kbs: usize, burst: Option<usize>) -> (u64, u64) {
+    let rate = kbs as u64 * 1024;
+    (rate, burst.map(|v| v as u64 * 1024).unwrap_or(rate))
+}
+/// the up and down buckets of a limit
+pub fn buckets(limit: &Option<ProxyLimit>) -> (Bucket, Bucket) {
+    let (up, down) = (Bucket::new(0, 0), Bucket::new(0, 0));
+    set_buckets(limit, &up, &down);
+    (up, down)
+}
+fn set_buckets(limit: &Option<ProxyLimit>, up: &Bucket, down: &Bucket) {
+    match limit {
+        None => {
+            up.set(0, 0);
+            down.set(0, 0);
+        }
+        Some(v) => {
+            let (rate, burst) = rate_burst(v.up, v.burst);
+            up.set(rate, burst);
+            let (rate, burst) = rate_burst(v.down, v.burst);
+            down.set(rate, burst);
+        }
+    }
+}
+
+/// a bandwidth budget shared by all conns of a scope(a rule,a node or the server)
+pub struct Budget {
+    limit: Mutex<Option<ProxyLimit>>,
+    up: Bucket,
+    down: Bucket,
+}
+impl Budget {
+    pub fn new(limit: Option<ProxyLimit>) -> Arc<Self> {
+        let (up, down) = buckets(&limit);
+        Arc::new(Self {
+            limit: Mutex::new(limit),
+            up,
+            down,
+        })
+    }
+    pub fn set(&self, limit: Option<ProxyLimit>) {
+        set_buckets(&limit, &self.up, &self.down);
+        if let Ok(mut lkv) = self.limit.lock() {
+            *lkv = limit;
+        }
+    }
+    pub fn limit(&self) -> Option<ProxyLimit> {
+        self.limit.lock().ok().and_then(|v| v.clone())
+    }
+}
+
+/// accept caps of a rule,0 is no cap
+pub struct ConnCaps {
+    max: u32,
+    per_ip: u32,
+    rate: Bucket,
+    st: Mutex<CapsState>,
+}
+struct CapsState {
+    conns: u32,
+    ips: HashMap<IpAddr, u32>,
+}
+/// a conn held in the caps until dropped
+pub struct CapGuard {
+    caps: Arc<ConnCaps>,
+    ip: Option<IpAddr>,
+}
+impl ConnCaps {
+    /// rate: new conns per second
+    pub fn new(max: u32, per_ip: u32, rate: u32) -> Arc<Self> {
+        Arc::new(Self {
+            max,
+            per_ip,
+            rate: Bucket::new(rate as u64, rate as u64),
+            st: Mutex::new(CapsState {
+                conns: 0,
+                ips: HashMap::new(),
+            }),
+        })
+    }
+    pub fn acquire(caps: &Arc<Self>, ip: Option<IpAddr>) -> Result<CapGuard, &'static str> {
+        let mut st = match caps.st.lock() {
+            Err(_) => return Err("lock err"),
+            Ok(v) => v,
+        };
+        if caps.max > 0 && st.conns >= caps.max {
+            return Err("max conns");
+        }
+        let ipn = ip.and_then(|v| st.ips.get(&v).cloned()).unwrap_or(0);
+        if caps.per_ip > 0 && ipn >= caps.per_ip {
+            return Err("max conns per ip");
+        }
+        if !caps.rate.try_take(1) {
+            return Err("conn rate");
+        }
+        st.conns += 1;
+        if let Some(v) = ip {
+            *st.ips.entry(v).or_insert(0) += 1;
+        }
+        Ok(CapGuard {
+            caps: caps.clone(),
+            ip,
+        })
+    }
+}
+impl Drop for CapGuard {
+    fn drop(&mut self) {
+        if let Ok(mut st) = self.caps.st.lock() {
+            st.conns = st.conns.saturating_sub(1);
+            if let Some(ip) = &self.ip {
+                if let Some(n) = st.ips.get_mut(ip) {
+                    *n -= 1;
+                    if *n == 0 {
+                        st.ips.remove(ip);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// wait until n bytes from the client fit in the conn bucket and all budgets
+pub async fn waits_up(bkt: &Bucket, budgets: &[Arc<Budget>], n: usize) {
+    let wt = budgets.iter().map(|v| v.up.take(n)).fold(bkt.take(n), Duration::max);
+    if !wt.is_zero() {
+        task::sleep(wt).await;
+    }
+}
+/// wait until n bytes to the client fit in the conn bucket and all budgets
+pub async fn waits_down(bkt: &Bucket, budgets: &[Arc<Budget>], n: usize) {
+    let wt = budgets.iter().map(|v| v.down.take(n)).fold(bkt.take(n), Duration::max);
+    if !wt.is_zero() {
+        task::sleep(wt).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{buckets, Bucket, ConnCaps};
+    use crate::entity::util::ProxyLimit;
+
+    #[test]
+    fn takes() {
+        let now = Instant::now();
+        let bkt = Bucket::new(1000, 1000);
+        assert_eq!(bkt.take_at(1000, now), Duration::ZERO);
+        // two conns share the debt
+        assert_eq!(bkt.take_at(500, now), Duration::from_millis(500));
+        assert_eq!(bkt.take_at(500, now), Duration::from_millis(1000));
+        let now = now + Duration::from_secs(1);
+        assert_eq!(bkt.take_at(500, now), Duration::from_millis(500));
+        // refill is capped by the burst
+        let now = now + Duration::from_secs(10);
+        assert_eq!(bkt.take_at(1000, now), Duration::ZERO);
+
+        let bkt = Bucket::new(0, 0);
+        assert_eq!(bkt.take_at(1 << 30, now), Duration::ZERO);
+    }
+
+    #[test]
+    fn caps() {
+        let ip1 = Some("10.0.0.1".parse().unwrap());
+        let ip2 = Some("10.0.0.2".parse().unwrap());
+        let caps = ConnCaps::new(3, 2, 0);
+        let g1 = ConnCaps::acquire(&caps, ip1).unwrap();
+        let g2 = ConnCaps::acquire(&caps, ip1).unwrap();
+        assert_eq!(ConnCaps::acquire(&caps, ip1).err(), Some("max conns per ip"));
+        let g3 = ConnCaps::acquire(&caps, ip2).unwrap();
+        assert_eq!(ConnCaps::acquire(&caps, ip2).err(), Some("max conns"));
+        drop(g1);
+        let _g4 = ConnCaps::acquire(&caps, ip1).unwrap();
+        drop((g2, g3));
+
+        let caps = ConnCaps::new(0, 0, 2);
+        let _g1 = ConnCaps::acquire(&caps, ip1).unwrap();
+        let _g2 = ConnCaps::acquire(&caps, ip1).unwrap();
+        assert_eq!(ConnCaps::acquire(&caps, ip2).err(), Some("conn rate"));
+    }
+
+    // a sender waiting out each take,the rate it gets over 10MB
+    fn sends(bkt: &Bucket, total: usize, chunk: usize) -> f64 {
+        let start = Instant::now();
+        let mut now = start;
+        let mut n = 0;
+        while n < total {
+            now += bkt.take_at(chunk, now);
+            n += chunk;
+        }
+        n as f64 / (now - start).as_secs_f64()
+    }
+
+    #[test]
+    fn rates() {
+        let (up, down) = buckets(&Some(ProxyLimit {
+            up: 1024,
+            down: 256,
+            burst: Some(64),
+        }));
+        let rt = sends(&up, 10 << 20, 10240);
+        assert!((rt / (1024.0 * 1024.0) - 1.0).abs() < 0.02, "up rate:{}", rt);
+        let rt = sends(&down, 10 << 20, 10240);
+        assert!((rt / (256.0 * 1024.0) - 1.0).abs() < 0.02, "down rate:{}", rt);
+    }
+
+    // on the clock,sleep overshoots so only slower is let through.
+    // a busy box slows it down more,run it by hand with --ignored
+    #[test]
+    #[ignore]
+    fn clock_rates() {
+        let bkt = Bucket::new(200 * 1024, 10240);
+        // the burst goes at once
+        assert_eq!(bkt.take(10240), Duration::ZERO);
+        let start = Instant::now();
+        let mut n = 0;
+        while n < 100 * 1024 {
+            std::thread::sleep(bkt.take(10240));
+            n += 10240;
+        }
+        let rt = n as f64 / start.elapsed().as_secs_f64() / (200.0 * 1024.0);
+        assert!(rt > 0.85 && rt < 1.05, "clock rate:{}", rt);
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    fmt::Write,
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use async_std::{
+    net::{TcpListener, TcpStream},
+    task,
+};
+use futures::StreamExt;
+
+use crate::entity::util::TrafficInfo;
+
+use super::{rule::RuleProxy, sniff, NodeServer, ServerCase};
+
+const WAIT_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// latency histogram in seconds
+#[derive(Default)]
+pub struct Histogram {
+    counts: [AtomicU64; 11],
+    sum_us: AtomicU64,
+    count: AtomicU64,
+}
+impl Histogram {
+    pub fn observe(&self, d: Duration) {
+        let secs = d.as_secs_f64();
+        if let Some(i) = WAIT_BUCKETS.iter().position(|v| secs <= *v) {
+            self.counts[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.sum_us.fetch_add(d.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// the prometheus text format,the samples of a family are written together after its head.
+pub struct MetricsText {
+    out: String,
+}
+impl MetricsText {
+    pub fn new() -> Self {
+        Self { out: String::new() }
+    }
+    pub fn family(&mut self, name: &str, tp: &str, help: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, tp);
+    }
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], v: f64) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            let ls: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+                .collect();
+            let _ = write!(self.out, "{{{}}}", ls.join(","));
+        }
+        let _ = writeln!(self.out, " {}", v);
+    }
+    pub fn histogram(&mut self, name: &str, labels: &[(&str, &str)], h: &Histogram) {
+        let bkts = format!("{}_bucket", name);
+        let mut n = 0;
+        for (i, b) in WAIT_BUCKETS.iter().enumerate() {
+            n += h.counts[i].load(Ordering::Relaxed);
+            let les = b.to_string();
+            let mut ls = labels.to_vec();
+            ls.push(("le", les.as_str()));
+            self.sample(bkts.as_str(), &ls, n as f64);
+        }
+        let count = h.count.load(Ordering::Relaxed);
+        let mut ls = labels.to_vec();
+        ls.push(("le", "+Inf"));
+        self.sample(bkts.as_str(), &ls, count as f64);
+        let sum = h.sum_us.load(Ordering::Relaxed) as f64 / 1e6;
+        self.sample(format!("{}_sum", name).as_str(), labels, sum);
+        self.sample(format!("{}_count", name).as_str(), labels, count as f64);
+    }
+    pub fn text(self) -> String {
+        self.out
+    }
+}
+fn escape(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn traffics(mt: &mut MetricsText, prefix: &str, label: &str, ls: &[(String, TrafficInfo)]) {
+    let name = format!("{}_bytes_total", prefix);
+    mt.family(name.as_str(), "counter", "bytes relayed,up is client to target");
+    for (k, v) in ls {
+        mt.sample(name.as_str(), &[(label, k.as_str()), ("direction", "up")], v.up as f64);
+        mt.sample(name.as_str(), &[(label, k.as_str()), ("direction", "down")], v.down as f64);
+    }
+    let name = format!("{}_conns_active", prefix);
+    mt.family(name.as_str(), "gauge", "conns being relayed");
+    for (k, v) in ls {
+        mt.sample(name.as_str(), &[(label, k.as_str())], v.active as f64);
+    }
+    let name = format!("{}_conns_total", prefix);
+    mt.family(name.as_str(), "counter", "conns relayed");
+    for (k, v) in ls {
+        mt.sample(name.as_str(), &[(label, k.as_str())], v.total as f64);
+    }
+    let name = format!("{}_dial_fails_total", prefix);
+    mt.family(name.as_str(), "counter", "failed dials to a target");
+    for (k, v) in ls {
+        mt.sample(name.as_str(), &[(label, k.as_str())], v.dial_fails as f64);
+    }
+    let name = format!("{}_rejects_total", prefix);
+    mt.family(name.as_str(), "counter", "conns refused by a cap");
+    for (k, v) in ls {
+        mt.sample(name.as_str(), &[(label, k.as_str())], v.rejects as f64);
+    }
+    let name = format!("{}_route_misses_total", prefix);
+    mt.family(name.as_str(), "counter", "conns no route takes");
+    for (k, v) in ls {
+        mt.sample(name.as_str(), &[(label, k.as_str())], v.misses as f64);
+    }
+}
+
+pub fn render(nodes: &[NodeServer], rules: &[RuleProxy], auth_fails: &[(&str, u64)]) -> String {
+    let mut mt = MetricsText::new();
+    mt.family("hbproxy_node_online", "gauge", "1 if the node is online");
+    for v in nodes {
+        let on = if v.online() { 1.0 } else { 0.0 };
+        mt.sample("hbproxy_node_online", &[("node", v.conf().name.as_str())], on);
+    }
+    mt.family(
+        "hbproxy_node_heartbeat_age_seconds",
+        "gauge",
+        "seconds since the last heartbeat of the node",
+    );
+    for v in nodes {
+        let age = v.heart_age().as_secs_f64();
+        let ls = [("node", v.conf().name.as_str())];
+        mt.sample("hbproxy_node_heartbeat_age_seconds", &ls, age);
+    }
+    mt.family(
+        "hbproxy_node_wait_conn_seconds",
+        "histogram",
+        "time to get a conn to a target from the node",
+    );
+    for v in nodes {
+        let ls = [("node", v.conf().name.as_str())];
+        mt.histogram("hbproxy_node_wait_conn_seconds", &ls, v.wait_hist());
+    }
+    let ls: Vec<(String, TrafficInfo)> = nodes
+        .iter()
+        .map(|v| (v.conf().name.clone(), v.traffic().info()))
+        .collect();
+    traffics(&mut mt, "hbproxy_node", "node", &ls);
+
+    mt.family("hbproxy_rule_status", "gauge", "0 starting,1 running,2 closed,-1 bind err");
+    for v in rules {
+        let ls = [("rule", v.conf().name.as_str())];
+        mt.sample("hbproxy_rule_status", &ls, v.status() as f64);
+    }
+    let ls: Vec<(String, TrafficInfo)> = rules
+        .iter()
+        .map(|v| (v.conf().name.clone(), v.traffic()))
+        .collect();
+    traffics(&mut mt, "hbproxy_rule", "rule", &ls);
+
+    mt.family("hbproxy_auth_fails_total", "counter", "requests with a bad sign");
+    for (k, v) in auth_fails {
+        mt.sample("hbproxy_auth_fails_total", &[("server", *k)], *v as f64);
+    }
+    mt.text()
+}
+
+/// serve /metrics and /healthz over plain http
+pub async fn run_metrics(ctx: ruisutil::Context, addrs: String, cs: ServerCase) -> io::Result<()> {
+    let lsr = TcpListener::bind(addrs.as_str()).await?;
+    log::info!("metrics start on:{}", addrs.as_str());
+    let mut incom = lsr.incoming();
+    while !ctx.done() {
+        match incom.next().await {
+            None => break,
+            Some(Err(e)) => {
+                log::error!("metrics conn err:{}", e);
+                break;
+            }
+            Some(Ok(conn)) => {
+                let cs = cs.clone();
+                task::spawn(async move {
+                    if let Err(e) = serve_metrics(conn, cs).await {
+                        log::debug!("metrics serve err:{}", e);
+                    }
+                });
+            }
+        }
+    }
+    Ok(())
+}
+async fn serve_metrics(mut conn: TcpStream, cs: ServerCase) -> io::Result<()> {
+    let bts = sniff::read_http_head(&mut conn).await?;
+    let path = match sniff::parse_http_head(&bts) {
+        None => {
+            sniff::http_error(&mut conn, 400, "Bad Request").await;
+            return Ok(());
+        }
+        Some((_, v)) => v,
+    };
+    match path.split('?').next().unwrap_or("") {
+        "/metrics" => {
+            let body = cs.metrics().await;
+            let ctype = "text/plain; version=0.0.4; charset=utf-8";
+            sniff::http_reply(&mut conn, 200, "OK", ctype, body.as_str()).await;
+        }
+        "/healthz" => sniff::http_reply(&mut conn, 200, "OK", "text/plain", "ok\n").await,
+        _ => sniff::http_error(&mut conn, 404, "Not Found").await,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Histogram, MetricsText};
+
+    #[test]
+    fn metrics_texts() {
+        let h = Histogram::default();
+        h.observe(Duration::from_millis(3));
+        h.observe(Duration::from_millis(300));
+        h.observe(Duration::from_secs(30));
+        let mut mt = MetricsText::new();
+        mt.family("wait_seconds", "histogram", "wait");
+        mt.histogram("wait_seconds", &[("node", "a\"b")], &h);
+        let txt = mt.text();
+        assert!(txt.starts_with("# HELP wait_seconds wait\n# TYPE wait_seconds histogram\n"));
+        assert!(txt.contains("wait_seconds_bucket{node=\"a\\\"b\",le=\"0.005\"} 1\n"));
+        assert!(txt.contains("wait_seconds_bucket{node=\"a\\\"b\",le=\"0.5\"} 2\n"));
+        assert!(txt.contains("wait_seconds_bucket{node=\"a\\\"b\",le=\"+Inf\"} 3\n"));
+        assert!(txt.contains("wait_seconds_count{node=\"a\\\"b\"} 3\n"));
+        assert!(txt.contains("wait_seconds_sum{node=\"a\\\"b\"} 30.303\n"));
+    }
+}
@@ -1,11 +1,27 @@
+mod balance;
 mod client;
+mod conn;
+mod conns;
+mod deflate;
+mod health;
+mod limiter;
+mod metrics;
+mod mux;
 mod node;
 mod nodes;
 mod proxy;
+mod proxy_proto;
 mod proxyer;
 mod rule;
+mod seal;
+mod sniff;
+mod stats;
 mod server_case;
+pub mod tls;
+mod udper;
+mod watch;
 
+pub use metrics::run_metrics;
 pub use server_case::ServerCase;
 
 pub use node::NodeServer;
@@ -14,6 +30,7 @@ pub use nodes::NodeEngine;
 
 pub use client::NodeClient;
 pub use client::NodeClientCfg;
+pub use client::NodeVisitCfg;
 
 pub use proxy::ProxyEngine;
 
@@ -0,0 +1,475 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::Shutdown,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use async_std::{
+    sync::{Mutex, RwLock},
+    task,
+};
+use ruisutil::ArcMut;
+
+use crate::utils::{
+    msg::{Message, Messages},
+    Notify, NOTIFY_RECHECK,
+};
+
+// control codes carried on the node control conn,cmds is the stream xids
+// node->server: local conn result,heads:"ok"/"err",an ok may add the tunnel options "+deflate+seal"
+pub const MUX_CTRL_CONN: i32 = 2;
+pub const MUX_CTRL_DATA: i32 = 3; // bodys:data
+pub const MUX_CTRL_WINDOW: i32 = 4; // heads:u32 BE,bytes the receiver consumed
+pub const MUX_CTRL_CLOSE: i32 = 5;
+pub const MUX_CTRL_VISIT: i32 = 6; // node->server: a visitor stream,bodys:NodeVisitMsg
+pub const MUX_CTRL_FIN: i32 = 7; // the sender writes no more,it still reads
+
+const MUX_WINDOW: usize = 1024 * 256;
+const MUX_FRAME_MAX: usize = 1024 * 32;
+
+/// a conn result with the tunnel options the node took
+pub fn conn_heads(ok: bool, compress: bool, encrypt: bool) -> String {
+    let mut rts = if ok { "ok" } else { "err" }.to_string();
+    if ok && compress {
+        rts.push_str("+deflate");
+    }
+    if ok && encrypt {
+        rts.push_str("+seal");
+    }
+    rts
+}
+/// the deflate and seal options of an ok conn result,None when it is not ok
+pub fn conn_opts(heads: &[u8]) -> Option<(bool, bool)> {
+    let mut ls = std::str::from_utf8(heads).ok()?.split('+');
+    if ls.next() != Some("ok") {
+        return None;
+    }
+    let ls: Vec<&str> = ls.collect();
+    Some((ls.contains(&"deflate"), ls.contains(&"seal")))
+}
+
+#[derive(Clone)]
+pub struct MuxSession {
+    inner: ArcMut<Inner>,
+}
+struct Inner {
+    ctx: ruisutil::Context,
+    msgs: Mutex<VecDeque<Messages>>,
+    // the owner's send notify,fired on every queued frame
+    sends: Notify,
+    streams: RwLock<HashMap<String, MuxStream>>,
+}
+
+impl MuxSession {
+    pub fn new(ctx: ruisutil::Context, sends: Notify) -> Self {
+        Self {
+            inner: ArcMut::new(Inner {
+                ctx: ruisutil::Context::background(Some(ctx)),
+                msgs: Mutex::new(VecDeque::new()),
+                sends,
+                streams: RwLock::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub async fn pop_msg(&self) -> Option<Messages> {
+        let mut lkv = self.inner.msgs.lock().await;
+        lkv.pop_front()
+    }
+    async fn push_msg(&self, msg: Messages) {
+        {
+            let mut lkv = self.inner.msgs.lock().await;
+            lkv.push_back(msg);
+        }
+        self.inner.sends.notify();
+    }
+
+    pub async fn open(&self, xids: &str) -> MuxStream {
+        let stream = MuxStream::new(self.clone(), xids.to_string());
+        let mut lkv = self.inner.streams.write().await;
+        lkv.insert(xids.to_string(), stream.clone());
+        stream
+    }
+    pub async fn get(&self, xids: &String) -> Option<MuxStream> {
+        let lkv = self.inner.streams.read().await;
+        lkv.get(xids).cloned()
+    }
+    async fn remove(&self, xids: &String) {
+        let mut lkv = self.inner.streams.write().await;
+        lkv.remove(xids);
+    }
+    pub async fn close(&self, xids: &String) {
+        if let Some(v) = self.get(xids).await {
+            v.close();
+        }
+    }
+    pub async fn close_all(&self) {
+        let mut lkv = self.inner.streams.write().await;
+        for (_, v) in lkv.iter() {
+            v.end_read();
+        }
+        lkv.clear();
+        let mut lkv = self.inner.msgs.lock().await;
+        lkv.clear();
+    }
+
+    pub async fn send_conn(&self, xids: &str, ok: bool, compress: bool, encrypt: bool) {
+        let heads = conn_heads(ok, compress, encrypt);
+        self.push_msg(Messages {
+            control: MUX_CTRL_CONN,
+            cmds: Some(xids.to_string()),
+            heads: Some(heads.as_bytes().into()),
+            bodys: None,
+        })
+        .await;
+    }
+
+    pub async fn send_visit(&self, xids: &str, bds: Box<[u8]>) {
+        self.push_msg(Messages {
+            control: MUX_CTRL_VISIT,
+            cmds: Some(xids.to_string()),
+            heads: None,
+            bodys: Some(bds),
+        })
+        .await;
+    }
+
+    /// handle data/window/close frames,conn results are left to the owner.
+    pub async fn on_msg(&self, msg: Message) {
+        let stream = match self.get(&msg.cmds).await {
+            None => {
+                log::debug!("mux stream {} not found", msg.cmds.as_str());
+                return;
+            }
+            Some(v) => v,
+        };
+        match msg.control {
+            MUX_CTRL_DATA => {
+                if let Some(bds) = msg.bodys {
+                    stream.on_data(bds).await;
+                }
+            }
+            MUX_CTRL_WINDOW => {
+                if let Some(hds) = msg.heads {
+                    if hds.len() == 4 {
+                        let n = u32::from_be_bytes([hds[0], hds[1], hds[2], hds[3]]);
+                        stream.inner.wind.fetch_add(n as usize, Ordering::SeqCst);
+                        stream.inner.writable.notify();
+                    }
+                }
+            }
+            MUX_CTRL_FIN => stream.on_fin(),
+            MUX_CTRL_CLOSE => {
+                stream.end_read();
+                self.remove(&msg.cmds).await;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// a logical stream on a MuxSession,each side may send up to MUX_WINDOW
+/// bytes the other side has not consumed yet.
+#[derive(Clone)]
+pub struct MuxStream {
+    inner: ArcMut<StreamInner>,
+}
+struct StreamInner {
+    sess: MuxSession,
+    xids: String,
+    bufs: Mutex<VecDeque<Box<[u8]>>>,
+    bufpos: AtomicUsize,
+    wind: AtomicUsize,
+    unacked: AtomicUsize,
+    // received and not given back by a window frame yet,a peer past MUX_WINDOW ignores it
+    recvd: AtomicUsize,
+    // fired on data or window,closed once the stream ends
+    readable: Notify,
+    writable: Notify,
+
+    endr: AtomicBool,
+    shutr: AtomicBool,
+    // the peer sent fin,we sent fin
+    finr: AtomicBool,
+    finw: AtomicBool,
+    closed: AtomicBool,
+}
+
+impl MuxStream {
+    fn new(sess: MuxSession, xids: String) -> Self {
+        Self {
+            inner: ArcMut::new(StreamInner {
+                sess,
+                xids,
+                bufs: Mutex::new(VecDeque::new()),
+                bufpos: AtomicUsize::new(0),
+                wind: AtomicUsize::new(MUX_WINDOW),
+                unacked: AtomicUsize::new(0),
+                recvd: AtomicUsize::new(0),
+                readable: Notify::new(),
+                writable: Notify::new(),
+
+                endr: AtomicBool::new(false),
+                shutr: AtomicBool::new(false),
+                finr: AtomicBool::new(false),
+                finw: AtomicBool::new(false),
+                closed: AtomicBool::new(false),
+            }),
+        }
+    }
+    fn done(&self) -> bool {
+        self.inner.closed.load(Ordering::SeqCst) || self.inner.sess.inner.ctx.done()
+    }
+
+    fn end_read(&self) {
+        self.inner.endr.store(true, Ordering::SeqCst);
+        self.inner.readable.close();
+        self.inner.writable.close();
+    }
+
+    async fn on_data(&self, bts: Box<[u8]>) {
+        if self.done() || bts.is_empty() {
+            return;
+        }
+        let rn = self.inner.recvd.fetch_add(bts.len(), Ordering::SeqCst) + bts.len();
+        if rn > MUX_WINDOW {
+            log::warn!("mux stream {} peer over window:{},reset", self.inner.xids.as_str(), rn);
+            self.close();
+            return;
+        }
+        {
+            let mut lkv = self.inner.bufs.lock().await;
+            lkv.push_back(bts);
+        }
+        self.inner.readable.notify();
+    }
+    // reads end once the buffered data is taken,the write side stays open
+    fn on_fin(&self) {
+        self.inner.finr.store(true, Ordering::SeqCst);
+        self.inner.readable.close();
+        if self.inner.finw.load(Ordering::SeqCst) {
+            self.close();
+        }
+    }
+
+    pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.inner.shutr.load(Ordering::SeqCst) || self.done() {
+                return Ok(0);
+            }
+            let n = {
+                let mut lkv = self.inner.bufs.lock().await;
+                let mut n = 0;
+                if let Some(v) = lkv.front() {
+                    let pos = self.inner.bufpos.load(Ordering::SeqCst);
+                    n = std::cmp::min(buf.len(), v.len() - pos);
+                    buf[..n].copy_from_slice(&v[pos..pos + n]);
+                    if pos + n >= v.len() {
+                        lkv.pop_front();
+                        self.inner.bufpos.store(0, Ordering::SeqCst);
+                    } else {
+                        self.inner.bufpos.store(pos + n, Ordering::SeqCst);
+                    }
+                }
+                n
+            };
+            if n > 0 {
+                let un = self.inner.unacked.fetch_add(n, Ordering::SeqCst) + n;
+                if un >= MUX_WINDOW / 2 {
+                    self.inner.unacked.fetch_sub(un, Ordering::SeqCst);
+                    self.inner.recvd.fetch_sub(un, Ordering::SeqCst);
+                    self.inner
+                        .sess
+                        .push_msg(Messages {
+                            control: MUX_CTRL_WINDOW,
+                            cmds: Some(self.inner.xids.clone()),
+                            heads: Some((un as u32).to_be_bytes().into()),
+                            bodys: None,
+                        })
+                        .await;
+                }
+                return Ok(n);
+            }
+            if self.inner.endr.load(Ordering::SeqCst) || self.inner.finr.load(Ordering::SeqCst) {
+                return Ok(0);
+            }
+            self.inner.readable.wait(NOTIFY_RECHECK).await;
+        }
+    }
+
+    pub async fn write_all(&self, bts: &[u8]) -> io::Result<()> {
+        let mut pos = 0;
+        while pos < bts.len() {
+            if self.done() || self.inner.endr.load(Ordering::SeqCst) {
+                return Err(ruisutil::ioerr("mux stream is closed", None));
+            }
+            if self.inner.finw.load(Ordering::SeqCst) {
+                return Err(ruisutil::ioerr("mux stream write is shutdown", None));
+            }
+            let wind = self.inner.wind.load(Ordering::SeqCst);
+            if wind == 0 {
+                self.inner.writable.wait(NOTIFY_RECHECK).await;
+                continue;
+            }
+            let n = std::cmp::min(std::cmp::min(bts.len() - pos, wind), MUX_FRAME_MAX);
+            self.inner.wind.fetch_sub(n, Ordering::SeqCst);
+            self.inner
+                .sess
+                .push_msg(Messages {
+                    control: MUX_CTRL_DATA,
+                    cmds: Some(self.inner.xids.clone()),
+                    heads: None,
+                    bodys: Some(bts[pos..pos + n].into()),
+                })
+                .await;
+            pos += n;
+        }
+        Ok(())
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match how {
+            Shutdown::Read => {
+                self.inner.shutr.store(true, Ordering::SeqCst);
+                self.inner.readable.close();
+            }
+            Shutdown::Write => self.fin(),
+            Shutdown::Both => self.close(),
+        }
+        Ok(())
+    }
+    fn fin(&self) {
+        if self.done() || self.inner.finw.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if self.inner.finr.load(Ordering::SeqCst) {
+            self.close();
+            return;
+        }
+        let c = self.clone();
+        task::spawn(async move {
+            c.inner
+                .sess
+                .push_msg(Messages {
+                    control: MUX_CTRL_FIN,
+                    cmds: Some(c.inner.xids.clone()),
+                    heads: None,
+                    bodys: None,
+                })
+                .await;
+        });
+    }
+    fn close(&self) {
+        if self.inner.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.inner.readable.close();
+        self.inner.writable.close();
+        let c = self.clone();
+        task::spawn(async move {
+            c.inner.sess.remove(&c.inner.xids).await;
+            if !c.inner.endr.load(Ordering::SeqCst) {
+                c.inner
+                    .sess
+                    .push_msg(Messages {
+                        control: MUX_CTRL_CLOSE,
+                        cmds: Some(c.inner.xids.clone()),
+                        heads: None,
+                        bodys: None,
+                    })
+                    .await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Shutdown, time::Duration};
+
+    use async_std::task;
+
+    use super::{conn_heads, conn_opts, MuxSession, MUX_CTRL_CLOSE, MUX_CTRL_DATA, MUX_WINDOW};
+    use crate::utils::{msg::Message, Notify};
+
+    fn session() -> MuxSession {
+        MuxSession::new(ruisutil::Context::background(None), Notify::new())
+    }
+    // hand the frames queued on from to the other end,the controls are returned
+    async fn pump(from: &MuxSession, to: &MuxSession) -> Vec<i32> {
+        task::sleep(Duration::from_millis(20)).await;
+        let mut rts = Vec::new();
+        while let Some(v) = from.pop_msg().await {
+            rts.push(v.control);
+            let mut msg = Message::new();
+            msg.control = v.control;
+            msg.cmds = v.cmds.unwrap_or_default();
+            msg.heads = v.heads;
+            msg.bodys = v.bodys;
+            to.on_msg(msg).await;
+        }
+        rts
+    }
+
+    #[test]
+    fn over_windows() {
+        task::block_on(async {
+            let (a, b) = (session(), session());
+            let sb = b.open("s1").await;
+            let mut msg = Message::new();
+            msg.control = MUX_CTRL_DATA;
+            msg.cmds = "s1".to_string();
+            msg.bodys = Some(vec![0u8; MUX_WINDOW].into_boxed_slice());
+            b.on_msg(msg).await;
+            assert!(pump(&b, &a).await.is_empty());
+            // a peer that ignores the window gets the stream reset
+            let mut msg = Message::new();
+            msg.control = MUX_CTRL_DATA;
+            msg.cmds = "s1".to_string();
+            msg.bodys = Some(vec![0u8; 1].into_boxed_slice());
+            b.on_msg(msg).await;
+            assert_eq!(pump(&b, &a).await, vec![MUX_CTRL_CLOSE]);
+            assert!(b.get(&"s1".to_string()).await.is_none());
+            let mut buf = [0u8; 16];
+            assert_eq!(sb.read(&mut buf).await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn half_closes() {
+        task::block_on(async {
+            let (a, b) = (session(), session());
+            let (sa, sb) = (a.open("s1").await, b.open("s1").await);
+            sa.write_all(b"abc").await.unwrap();
+            sa.shutdown(Shutdown::Write).unwrap();
+            pump(&a, &b).await;
+            let mut buf = [0u8; 16];
+            assert_eq!(sb.read(&mut buf).await.unwrap(), 3);
+            assert_eq!(sb.read(&mut buf).await.unwrap(), 0);
+            assert!(sa.write_all(b"x").await.is_err());
+            // the other way is still open
+            sb.write_all(b"def").await.unwrap();
+            pump(&b, &a).await;
+            assert_eq!(sa.read(&mut buf).await.unwrap(), 3);
+            assert_eq!(&buf[..3], b"def");
+            sb.shutdown(Shutdown::Write).unwrap();
+            assert_eq!(pump(&b, &a).await, vec![MUX_CTRL_CLOSE]);
+            assert_eq!(sa.read(&mut buf).await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn conn_results() {
+        // an old node answers ok/err only
+        assert_eq!(conn_opts(b"ok"), Some((false, false)));
+        assert_eq!(conn_opts(b"err"), None);
+        assert_eq!(conn_opts(b""), None);
+        for (zip, seal) in [(false, false), (true, false), (false, true), (true, true)] {
+            let hs = conn_heads(true, zip, seal);
+            assert_eq!(conn_opts(hs.as_bytes()), Some((zip, seal)), "{}", hs);
+            assert_eq!(conn_heads(false, zip, seal), "err");
+        }
+        assert_eq!(conn_heads(true, true, true), "ok+deflate+seal");
+    }
+}
@@ -1,6 +1,10 @@
 use std::{
     collections::{HashMap, VecDeque},
     io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex as StdMutex,
+    },
     time::{Duration, SystemTime},
 };
 
@@ -11,17 +15,27 @@ use async_std::{
 };
 
 use crate::{
-    entity::node::NodeConnMsg,
-    utils::{self, msg::Messages},
+    app::Application,
+    entity::node::{NodeConnMsg, NodeVisitMsg, ProxyProtoMsg},
+    utils::{self, msg::Messages, Notify, NOTIFY_RECHECK},
 };
 
-use super::NodeEngine;
+use super::{
+    conn::ProxyConn, health, metrics::Histogram, mux, mux::MuxSession, stats::TrafficStat, tls,
+    NodeEngine,
+};
 
 pub struct NodeServerCfg {
     pub id: String,
     pub name: String,
     pub version: Option<String>,
     pub token: String,
+    pub mux: bool,
+    pub probe: bool,
+    // concurrent tunnels,0 is no cap
+    pub max_tunnels: u32,
+    // the key the node signs with,encrypted tunnels are sealed with it
+    pub key: Option<String>,
 }
 #[derive(Clone)]
 pub struct NodeServer {
@@ -37,15 +51,62 @@ struct Inner {
     ctmout: ruisutil::Timer,
 
     msgs: Mutex<VecDeque<Messages>>,
+    // fired on every queued msg,closed with the conn
+    sends: Notify,
     waits: RwLock<HashMap<String, Mutex<WaitItem>>>,
+    // the probe result and the notify fired when it comes
+    probes: Mutex<HashMap<String, (i8, Notify)>>,
+    // idle data conns the node parked and when,claimed before asking for a new one
+    pool: StdMutex<VecDeque<(SystemTime, TcpStream)>>,
+    mux: Option<MuxSession>,
+    traffic: Arc<TrafficStat>,
+    wait_hist: Histogram,
 
     oln_time: SystemTime,
     otln_time: SystemTime,
+    // ms since the unix epoch,read by metrics while on_msg sets it
+    heart_time: AtomicU64,
+}
+
+// parked conns kept per node at most
+const NODE_POOL_MAX: usize = 64;
+/// the node parks a new conn after one is idle this long
+pub const NODE_PARK_IDLE: Duration = Duration::from_secs(60);
+// the server drops a parked conn earlier,a claim never meets a conn the node gave up on
+const NODE_PARK_EXPIRE: Duration = Duration::from_secs(50);
+// a parked conn must answer a claim in this,else the claim falls back to NodeConnMsg
+const NODE_CLAIM_TMOUT: Duration = Duration::from_secs(1);
+
+// take the parked conns idle past NODE_PARK_EXPIRE at now,the oldest are in front
+fn expired<T>(pool: &mut VecDeque<(SystemTime, T)>, now: SystemTime) -> Vec<T> {
+    let mut rts = Vec::new();
+    while let Some((tms, _)) = pool.front() {
+        if now.duration_since(*tms).unwrap_or_default() < NODE_PARK_EXPIRE {
+            break;
+        }
+        if let Some((_, v)) = pool.pop_front() {
+            rts.push(v);
+        }
+    }
+    rts
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|v| v.as_millis() as u64)
+        .unwrap_or_default()
 }
 
 struct WaitItem {
     pub stat: i8,
-    pub conn: Option<TcpStream>,
+    pub conn: Option<ProxyConn>,
+    // asked for,then what the node answered
+    pub compress: bool,
+    pub encrypt: bool,
+    pub encrypted: bool,
+    // fired once the node answers
+    pub ready: Notify,
 }
 
 impl NodeServer {
@@ -56,9 +117,16 @@ impl NodeServer {
         mut cfg: NodeServerCfg,
     ) -> Self {
         cfg.id = xid::new().to_string();
+        let ctx = ruisutil::Context::background(Some(ctx));
+        let sends = Notify::new();
+        let mux = if cfg.mux {
+            Some(MuxSession::new(ctx.clone(), sends.clone()))
+        } else {
+            None
+        };
         Self {
             inner: ruisutil::ArcMut::new(Inner {
-                ctx: ruisutil::Context::background(Some(ctx)),
+                ctx,
                 egn: egn,
                 cfg: cfg,
                 conn: conn,
@@ -66,9 +134,16 @@ impl NodeServer {
                 ctmout: ruisutil::Timer::new(Duration::from_secs(30)),
 
                 msgs: Mutex::new(VecDeque::new()),
+                sends,
                 waits: RwLock::new(HashMap::new()),
+                probes: Mutex::new(HashMap::new()),
+                pool: StdMutex::new(VecDeque::new()),
+                mux,
+                traffic: TrafficStat::new(),
+                wait_hist: Histogram::default(),
                 oln_time: SystemTime::now(),
                 otln_time: SystemTime::UNIX_EPOCH,
+                heart_time: AtomicU64::new(unix_millis()),
             }),
         }
     }
@@ -77,12 +152,33 @@ impl NodeServer {
         &self.inner.cfg
     }
 
+    pub fn version_since(&self, vers: &str) -> bool {
+        match &self.inner.cfg.version {
+            None => false,
+            Some(v) => matches!(
+                utils::compare_version(v, vers.into()),
+                utils::CompareVersion::Great | utils::CompareVersion::Eq
+            ),
+        }
+    }
+
+    pub fn traffic(&self) -> &Arc<TrafficStat> {
+        &self.inner.traffic
+    }
+    pub fn wait_hist(&self) -> &Histogram {
+        &self.inner.wait_hist
+    }
+    pub fn heart_age(&self) -> Duration {
+        let tms = self.inner.heart_time.load(Ordering::SeqCst);
+        Duration::from_millis(unix_millis().saturating_sub(tms))
+    }
+
     pub fn peer_addr(&self) -> io::Result<String> {
         if self.inner.shuted {
             return Err(ruisutil::ioerr("conn is shutdown", None));
         }
         let addr = self.inner.conn.peer_addr()?;
-        Ok(addr.to_string())
+        Ok(tls::real_addr(addr.to_string()))
     }
 
     fn close(&self) {
@@ -95,6 +191,18 @@ impl NodeServer {
         if let Err(e) = ins.conn.shutdown(std::net::Shutdown::Both) {
             log::error!("close shutdown err:{}", e);
         }
+        self.inner.sends.close();
+        if let Some(mux) = &self.inner.mux {
+            let mux = mux.clone();
+            task::spawn(async move {
+                mux.close_all().await;
+            });
+        }
+        if let Ok(mut lkv) = self.inner.pool.lock() {
+            for (_, v) in lkv.drain(..) {
+                let _ = v.shutdown(std::net::Shutdown::Both);
+            }
+        }
     }
     pub fn stop(&self) {
         self.inner.ctx.stop();
@@ -142,6 +250,27 @@ impl NodeServer {
                     task::sleep(Duration::from_millis(100)).await;
                 }
                 Ok(v) => {
+                    // mux frames are handled in order,a visit stream must exist before its data
+                    if let Some(mux) = &self.inner.mux {
+                        match v.control {
+                            mux::MUX_CTRL_DATA
+                            | mux::MUX_CTRL_WINDOW
+                            | mux::MUX_CTRL_FIN
+                            | mux::MUX_CTRL_CLOSE => {
+                                mux.on_msg(v).await;
+                                continue;
+                            }
+                            mux::MUX_CTRL_VISIT => {
+                                let stream = mux.open(&v.cmds).await;
+                                let c = self.clone();
+                                task::spawn(async move {
+                                    c.on_visit(v, ProxyConn::Mux(stream)).await;
+                                });
+                                continue;
+                            }
+                            _ => {}
+                        }
+                    }
                     let c = self.clone();
                     task::spawn(async move {
                         c.on_msg(v).await;
@@ -156,10 +285,15 @@ impl NodeServer {
             if self.inner.shuted {
                 break;
             }
-            let msg = {
+            let mut msg = {
                 let mut lkv = self.inner.msgs.lock().await;
                 lkv.pop_front()
             };
+            if msg.is_none() {
+                if let Some(mux) = &self.inner.mux {
+                    msg = mux.pop_msg().await;
+                }
+            }
             if let Some(v) = msg {
                 if let Err(e) = utils::msg::send_msgs(&self.inner.ctx, &mut ins.conn, v).await {
                     log::error!("run_send send_msgs err:{}", e);
@@ -168,7 +302,7 @@ impl NodeServer {
                       continue;
                   } */
             } else {
-                task::sleep(Duration::from_millis(10)).await;
+                self.inner.sends.wait(NOTIFY_RECHECK).await;
             }
         }
     }
@@ -182,6 +316,7 @@ impl NodeServer {
                 self.stop();
             }
         }
+        self.expire_pool();
 
         Ok(())
     }
@@ -189,18 +324,67 @@ impl NodeServer {
         match msg.control {
             0 => {
                 self.inner.ctmout.reset();
+                self.inner.heart_time.store(unix_millis(), Ordering::SeqCst);
                 log::debug!("{} heart", self.inner.cfg.name.as_str());
-                let mut lkv = self.inner.msgs.lock().await;
-                lkv.push_front(Messages {
-                    control: 0,
-                    cmds: Some("heart".into()),
-                    heads: None,
-                    bodys: None,
-                })
+                {
+                    let mut lkv = self.inner.msgs.lock().await;
+                    lkv.push_front(Messages {
+                        control: 0,
+                        cmds: Some("heart".into()),
+                        heads: None,
+                        bodys: None,
+                    });
+                }
+                self.inner.sends.notify();
+            }
+            mux::MUX_CTRL_CONN => {
+                if let Some(mux) = &self.inner.mux {
+                    let opts = mux::conn_opts(msg.heads.as_deref().unwrap_or_default());
+                    let conn = match opts {
+                        Some(_) => mux.get(&msg.cmds).await.map(ProxyConn::Mux),
+                        None => None,
+                    };
+                    let (compress, encrypt) = opts.unwrap_or_default();
+                    if let Err(e) = self.put_conn(&msg.cmds, conn, compress, encrypt).await {
+                        log::debug!("mux put_conn {} err:{}", msg.cmds.as_str(), e);
+                        mux.close(&msg.cmds).await;
+                    }
+                }
+            }
+            health::NODE_CTRL_PROBED => {
+                let ok = match &msg.heads {
+                    None => false,
+                    Some(v) => &v[..] == "ok".as_bytes(),
+                };
+                let mut lkv = self.inner.probes.lock().await;
+                if let Some(v) = lkv.get_mut(&msg.cmds) {
+                    v.0 = if ok { 1 } else { -1 };
+                    v.1.notify();
+                }
             }
             _ => {}
         }
     }
+    async fn on_visit(&self, msg: utils::msg::Message, conn: ProxyConn) {
+        let data: NodeVisitMsg = match msg.bodys.map(|v| serde_json::from_slice(&v)) {
+            Some(Ok(v)) => v,
+            _ => {
+                let _ = conn.shutdown(std::net::Shutdown::Both);
+                return;
+            }
+        };
+        log::debug!(
+            "node {} visit rule:{}",
+            self.inner.cfg.name.as_str(),
+            data.rule.as_str()
+        );
+        match &Application::get().server_case {
+            None => {
+                let _ = conn.shutdown(std::net::Shutdown::Both);
+            }
+            Some(cs) => cs.visit(data, conn).await,
+        }
+    }
 
     pub fn online(&self) -> bool {
         if self.inner.shuted {
@@ -234,23 +418,133 @@ impl NodeServer {
         }
     }
 
-    pub async fn put_conn(&self, xids: &String, conn: Option<TcpStream>) -> io::Result<()> {
+    /// room for one more parked conn,a mux node has no use for them
+    pub fn parkable(&self) -> bool {
+        if self.inner.shuted || self.inner.mux.is_some() {
+            return false;
+        }
+        match self.inner.pool.lock() {
+            Err(_) => false,
+            Ok(lkv) => lkv.len() < NODE_POOL_MAX,
+        }
+    }
+    pub fn park(&self, conn: TcpStream) {
+        if let Ok(mut lkv) = self.inner.pool.lock() {
+            lkv.push_back((SystemTime::now(), conn));
+        }
+    }
+    fn expire_pool(&self) {
+        let mut lkv = match self.inner.pool.lock() {
+            Err(_) => return,
+            Ok(v) => v,
+        };
+        for v in expired(&mut lkv, SystemTime::now()) {
+            let _ = v.shutdown(std::net::Shutdown::Both);
+        }
+    }
+    /// a parked conn skips the dial back.
+    /// Ok(None) when none is left or the first one tried fails,the caller asks the node then
+    async fn claim(
+        &self,
+        xids: &str,
+        bds: &[u8],
+        compress: bool,
+        encrypt: bool,
+    ) -> io::Result<Option<ProxyConn>> {
+        self.expire_pool();
+        let conn = match self.inner.pool.lock() {
+            Err(_) => None,
+            Ok(mut lkv) => lkv.pop_front(),
+        };
+        let mut conn = match conn {
+            None => return Ok(None),
+            Some((_, v)) => v,
+        };
+        let ctx = &self.inner.ctx;
+        let bds = Some(bds.to_vec().into_boxed_slice());
+        let res = async_std::io::timeout(NODE_CLAIM_TMOUT, async {
+            utils::msg::send_msg(ctx, &mut conn, 1, None, None, bds).await?;
+            utils::msg::parse_msg(ctx, &mut conn).await
+        })
+        .await;
+        let msg = match res {
+            Err(e) => {
+                log::debug!("claim {} parked conn err:{}", self.inner.cfg.name.as_str(), e);
+                let _ = conn.shutdown(std::net::Shutdown::Both);
+                return Ok(None);
+            }
+            Ok(v) => v,
+        };
+        let (zip, seal) = match mux::conn_opts(msg.heads.as_deref().unwrap_or_default()) {
+            None => return Err(ruisutil::ioerr("local conn err", None)),
+            Some(v) => v,
+        };
+        if encrypt && !seal {
+            let _ = conn.shutdown(std::net::Shutdown::Both);
+            return Err(ruisutil::ioerr("node conn not encrypted", None));
+        }
+        let conn = self.tunnel(ProxyConn::Tcp(conn), xids, encrypt, compress && zip);
+        Ok(Some(conn))
+    }
+
+    /// compress,encrypt: the node deflates or seals the conn,an old node never says so
+    pub async fn put_conn(
+        &self,
+        xids: &String,
+        conn: Option<ProxyConn>,
+        compress: bool,
+        encrypt: bool,
+    ) -> io::Result<()> {
         let lkv = self.inner.waits.read().await;
         if let Some(mkv) = lkv.get(xids) {
             let mut v = mkv.lock().await;
             if let Some(cn) = conn {
                 v.stat = 1;
                 v.conn = Some(cn);
+                v.compress = v.compress && compress;
+                v.encrypted = encrypt;
             } else {
                 v.stat = -1;
                 // log::debug!("put_conn err stat=-1!!!!!!!!!!!!!!!!!");
             }
+            v.ready.notify();
             return Ok(());
         }
         Err(ruisutil::ioerr("timeout", None))
     }
-    pub async fn wait_conn(&self, host: &Option<String>, port: i32) -> io::Result<TcpStream> {
+    pub async fn wait_conn(
+        &self,
+        host: &Option<String>,
+        port: i32,
+        protocol: Option<&str>,
+        pp: Option<ProxyProtoMsg>,
+        compress: bool,
+        encrypt: bool,
+    ) -> io::Result<ProxyConn> {
         // let ins = unsafe { self.inner.muts() };
+        if protocol == Some("udp") && !self.version_since("0.4.0") {
+            return Err(ruisutil::ioerr("node version not support udp", None));
+        }
+        // an old node would drop the header and the target would read the client bytes as one
+        if pp.is_some() && !self.version_since("0.4.0") {
+            return Err(ruisutil::ioerr("node version not support proxy_protocol", None));
+        }
+        // never fall back to plain bytes for a goto that wants them sealed
+        if encrypt && self.inner.cfg.key.is_none() {
+            return Err(ruisutil::ioerr("encrypt needs a node key", None));
+        }
+        if encrypt && !self.version_since("0.4.0") {
+            return Err(ruisutil::ioerr("node version not support encrypt", None));
+        }
+        let max = self.inner.cfg.max_tunnels as usize;
+        if max > 0 {
+            let waitn = self.inner.waits.read().await.len();
+            if self.inner.traffic.info().active as usize + waitn >= max {
+                self.inner.traffic.reject();
+                return Err(ruisutil::ioerr("node tunnels are full", None));
+            }
+        }
+        let start = SystemTime::now();
         let mut xids;
         let mut rterr = ruisutil::ioerr("this is outline", None);
         {
@@ -270,6 +564,33 @@ impl NodeServer {
                 }
             }
         }
+        let bds = match serde_json::to_vec(&NodeConnMsg {
+            name: self.inner.cfg.name.clone(),
+            xids: xids.clone(),
+            host: host.clone(),
+            port: port,
+            protocol: protocol.map(|v| v.to_string()),
+            proxy_proto: pp,
+            compress: if compress { Some(true) } else { None },
+            encrypt: if encrypt { Some(true) } else { None },
+        }) {
+            Err(_) => return Err(ruisutil::ioerr("to json err", None)),
+            Ok(v) => v,
+        };
+        // a parked conn answers at once
+        match self.claim(&xids, &bds, compress, encrypt).await {
+            Ok(None) => {}
+            Ok(Some(conn)) => {
+                self.inner.wait_hist.observe(start.elapsed().unwrap_or_default());
+                return Ok(conn);
+            }
+            Err(e) => {
+                self.inner.traffic.dial_fail();
+                self.inner.wait_hist.observe(start.elapsed().unwrap_or_default());
+                return Err(e);
+            }
+        }
+        let ready = Notify::new();
         {
             let mut lkv = self.inner.waits.write().await;
             lkv.insert(
@@ -277,19 +598,17 @@ impl NodeServer {
                 Mutex::new(WaitItem {
                     stat: 0,
                     conn: None,
+                    compress,
+                    encrypt,
+                    encrypted: false,
+                    ready: ready.clone(),
                 }),
             );
         }
-        let bds = match serde_json::to_vec(&NodeConnMsg {
-            name: self.inner.cfg.name.clone(),
-            xids: xids.clone(),
-            host: host.clone(),
-            port: port,
-        }) {
-            Err(_) => return Err(ruisutil::ioerr("to json err", None)),
-            Ok(v) => v,
-        };
         if !self.inner.shuted {
+            if let Some(mux) = &self.inner.mux {
+                mux.open(&xids).await;
+            }
             {
                 let mut lkv = self.inner.msgs.lock().await;
                 lkv.push_back(Messages {
@@ -299,12 +618,12 @@ impl NodeServer {
                     bodys: Some(bds.into_boxed_slice()),
                 });
             }
+            self.inner.sends.notify();
 
             rterr = ruisutil::ioerr("timeout", None);
-            let ctx = ruisutil::Context::with_timeout(
-                Some(self.inner.ctx.clone()),
-                Duration::from_secs(10),
-            );
+            let tmout = Duration::from_secs(10);
+            let ctx = ruisutil::Context::with_timeout(Some(self.inner.ctx.clone()), tmout);
+            let tms = SystemTime::now();
             let mut rets = None;
             while !ctx.done() {
                 let mut stat = 0;
@@ -319,21 +638,140 @@ impl NodeServer {
                     let mut lkv = self.inner.waits.write().await;
                     if let Some(mkv) = lkv.remove(&xids) {
                         let mut v = mkv.lock().await;
-                        rets = std::mem::replace(&mut v.conn, None);
+                        let conn = v.conn.take();
+                        if v.encrypt && !v.encrypted {
+                            if let Some(conn) = conn {
+                                let _ = conn.shutdown(std::net::Shutdown::Both);
+                            }
+                            rterr = ruisutil::ioerr("node conn not encrypted", None);
+                            break;
+                        }
+                        rets = conn.map(|conn| self.tunnel(conn, &xids, v.encrypt, v.compress));
                         break;
                     }
                 } else if stat == -1 {
                     rterr = ruisutil::ioerr("local conn err", None);
                     break;
                 }
-                task::sleep(Duration::from_millis(10)).await;
+                let left = tmout.saturating_sub(tms.elapsed().unwrap_or_default());
+                ready.wait(left.min(NOTIFY_RECHECK)).await;
             }
             if let Some(conn) = rets {
+                self.inner.wait_hist.observe(start.elapsed().unwrap_or_default());
                 return Ok(conn);
             }
+            if let Some(mux) = &self.inner.mux {
+                mux.close(&xids).await;
+            }
         }
         let mut lkv = self.inner.waits.write().await;
         lkv.remove(&xids);
+        self.inner.traffic.dial_fail();
+        self.inner.wait_hist.observe(start.elapsed().unwrap_or_default());
         Err(rterr)
     }
+
+    fn tunnel(&self, conn: ProxyConn, xids: &str, encrypt: bool, compress: bool) -> ProxyConn {
+        let conn = match &self.inner.cfg.key {
+            Some(key) if encrypt => conn.sealed(key.as_str(), xids, true),
+            _ => conn,
+        };
+        if compress {
+            conn.deflated()
+        } else {
+            conn
+        }
+    }
+
+    /// ask the node to dial host:port,Unsupported if the node is too old to answer
+    pub async fn probe(&self, host: &Option<String>, port: i32) -> io::Result<()> {
+        if !self.inner.cfg.probe {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "node not support probe"));
+        }
+        if self.inner.shuted {
+            return Err(ruisutil::ioerr("this is outline", None));
+        }
+        let xids = format!("p{}", xid::new().to_string().as_str());
+        let bds = match serde_json::to_vec(&NodeConnMsg {
+            name: self.inner.cfg.name.clone(),
+            xids: xids.clone(),
+            host: host.clone(),
+            port,
+            protocol: None,
+            proxy_proto: None,
+            compress: None,
+            encrypt: None,
+        }) {
+            Err(_) => return Err(ruisutil::ioerr("to json err", None)),
+            Ok(v) => v,
+        };
+        let ready = Notify::new();
+        {
+            let mut lkv = self.inner.probes.lock().await;
+            lkv.insert(xids.clone(), (0, ready.clone()));
+        }
+        {
+            let mut lkv = self.inner.msgs.lock().await;
+            lkv.push_back(Messages {
+                control: health::NODE_CTRL_PROBE,
+                cmds: None,
+                heads: None,
+                bodys: Some(bds.into_boxed_slice()),
+            });
+        }
+        self.inner.sends.notify();
+        let ctx = ruisutil::Context::with_timeout(
+            Some(self.inner.ctx.clone()),
+            health::PROBE_TIMEOUT,
+        );
+        let tms = SystemTime::now();
+        let mut stat = 0;
+        while !ctx.done() {
+            {
+                let lkv = self.inner.probes.lock().await;
+                if let Some(v) = lkv.get(&xids) {
+                    stat = v.0;
+                }
+            }
+            if stat != 0 {
+                break;
+            }
+            let left = health::PROBE_TIMEOUT.saturating_sub(tms.elapsed().unwrap_or_default());
+            ready.wait(left.min(NOTIFY_RECHECK)).await;
+        }
+        let mut lkv = self.inner.probes.lock().await;
+        lkv.remove(&xids);
+        match stat {
+            1 => Ok(()),
+            -1 => Err(ruisutil::ioerr("target conn err", None)),
+            _ => Err(ruisutil::ioerr("probe timeout", None)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{expired, NODE_CLAIM_TMOUT, NODE_PARK_EXPIRE, NODE_PARK_IDLE};
+
+    #[test]
+    fn park_expires() {
+        // a claim the server starts on its last live tick ends before the node parks anew
+        assert!(NODE_PARK_EXPIRE + NODE_CLAIM_TMOUT < NODE_PARK_IDLE);
+        let tms = SystemTime::now();
+        let mut pool = VecDeque::new();
+        pool.push_back((tms, 1));
+        pool.push_back((tms + Duration::from_secs(30), 2));
+        let lives = tms + NODE_PARK_EXPIRE - Duration::from_millis(1);
+        assert!(expired(&mut pool, lives).is_empty());
+        assert_eq!(pool.front().map(|v| v.1), Some(1));
+        assert_eq!(expired(&mut pool, tms + NODE_PARK_EXPIRE), vec![1]);
+        assert_eq!(expired(&mut pool, tms + NODE_PARK_IDLE), Vec::<i32>::new());
+        assert_eq!(expired(&mut pool, tms + NODE_PARK_IDLE * 2), vec![2]);
+        assert!(pool.is_empty());
+    }
 }
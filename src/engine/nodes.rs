@@ -1,14 +1,39 @@
-use std::{collections::HashMap, io};
+use std::{
+    collections::HashMap,
+    io,
+    sync::{self, Arc},
+};
 
 use async_std::{net::TcpStream, sync::RwLock, task};
 
 use crate::{
-    engine::proxyer::{Proxyer, ProxyerCfg},
-    entity::node::{NodeConnMsg, NodeListIt, NodeListRep, ProxyGoto, RegNodeReq},
+    engine::{
+        conn::ProxyConn,
+        conns::ConnRegistry,
+        limiter::Budget,
+        proxyer::{Proxyer, ProxyerCfg},
+        stats::TrafficStat,
+    },
+    entity::{
+        node::{NodeListIt, NodeListRep, ProxyGoto, ProxyProtoMsg, RegNodeReq},
+        proxy::ConnListIt,
+        util::ProxyLimit,
+    },
 };
 
 use super::{NodeServer, NodeServerCfg};
 
+/// the rule side of a NodeEngine::proxy,all empty for a plain goto
+#[derive(Default)]
+pub struct ProxyOpts<'a> {
+    pub rule: Option<&'a str>,
+    // bytes already read from conn,they are sent to connlc first
+    pub firsts: Option<Box<[u8]>>,
+    // the rule stats and budgets,the node ones are added by proxy
+    pub stats: Vec<Arc<TrafficStat>>,
+    pub budgets: Vec<Arc<Budget>>,
+}
+
 #[derive(Clone)]
 pub struct NodeEngine {
     inner: ruisutil::ArcMut<Inner>,
@@ -17,6 +42,10 @@ pub struct NodeEngine {
 struct Inner {
     ctx: ruisutil::Context,
     nodes: RwLock<HashMap<String, NodeServer>>,
+    conns: ConnRegistry,
+    budget: Arc<Budget>,
+    // by node name,kept while the node reconnects
+    node_budgets: sync::Mutex<HashMap<String, Arc<Budget>>>,
 }
 
 impl NodeEngine {
@@ -25,6 +54,9 @@ impl NodeEngine {
             inner: ruisutil::ArcMut::new(Inner {
                 ctx: ruisutil::Context::background(Some(ctx)),
                 nodes: RwLock::new(HashMap::new()),
+                conns: ConnRegistry::new(),
+                budget: Budget::new(None),
+                node_budgets: sync::Mutex::new(HashMap::new()),
             }),
         }
     }
@@ -115,6 +147,7 @@ impl NodeEngine {
                 }
                 Ok(v) => Some(v),
             },
+            traffic: Some(v.traffic().info()),
         })
     }
 
@@ -143,11 +176,16 @@ impl NodeEngine {
                         }
                         Ok(v) => Some(v),
                     },
+                    traffic: Some(v.traffic().info()),
                 });
             }
         }
         Ok(rts)
     }
+    pub async fn nodes(&self) -> Vec<NodeServer> {
+        let lkv = self.inner.nodes.read().await;
+        lkv.values().cloned().collect()
+    }
     pub async fn remove(&self, name: &String, id: &String) {
         let mut lkv = self.inner.nodes.write().await;
         if let Some(v) = lkv.get(name) {
@@ -176,17 +214,38 @@ impl NodeEngine {
         &self,
         name: &String,
         xids: &String,
-        conn: Option<TcpStream>,
+        conn: Option<ProxyConn>,
+        compress: bool,
+        encrypt: bool,
     ) -> io::Result<()> {
         let lkv = self.inner.nodes.read().await;
         if let Some(v) = lkv.get(name) {
-            v.put_conn(xids, conn).await?;
+            v.put_conn(xids, conn, compress, encrypt).await?;
         }
         Ok(())
     }
-    pub async fn wait_connlc(&self, data: &ProxyGoto) -> io::Result<TcpStream> {
+    /// src,dst: the client and listener addrs,for a goto with proxy_protocol
+    pub async fn wait_connlc(
+        &self,
+        data: &ProxyGoto,
+        protocol: Option<&str>,
+        src: Option<String>,
+        dst: Option<String>,
+    ) -> io::Result<ProxyConn> {
         let v = self.find_node(&data.proxy_host).await?;
-        let connlc = match v.wait_conn(&data.localhost, data.proxy_port).await {
+        let pp = match &data.proxy_protocol {
+            Some(vs) if protocol != Some("udp") => Some(ProxyProtoMsg {
+                version: vs.clone(),
+                src,
+                dst,
+            }),
+            _ => None,
+        };
+        // udp frames are short and few,only tcp is deflated
+        let compress = data.compress == Some(true) && protocol != Some("udp");
+        let encrypt = data.encrypt == Some(true);
+        let (host, port) = (&data.localhost, data.proxy_port);
+        let connlc = match v.wait_conn(host, port, protocol, pp, compress, encrypt).await {
             Ok(v) => v,
             Err(e) => {
                 return Err(ruisutil::ioerr(
@@ -197,16 +256,93 @@ impl NodeEngine {
         };
         Ok(connlc)
     }
-    pub async fn proxy(&self, data: &ProxyGoto, conn: TcpStream, connlc: TcpStream) {
+    /// the stat of an online node
+    pub async fn traffic(&self, name: &String) -> Option<Arc<TrafficStat>> {
+        self.find_node(name).await.ok().map(|v| v.traffic().clone())
+    }
+    pub async fn probe(&self, data: &ProxyGoto) -> io::Result<()> {
+        let v = self.find_node(&data.proxy_host).await?;
+        v.probe(&data.localhost, data.proxy_port).await
+    }
+    pub async fn proxy(
+        &self,
+        data: &ProxyGoto,
+        conn: ProxyConn,
+        connlc: ProxyConn,
+        opts: ProxyOpts<'_>,
+    ) {
+        let ProxyOpts {
+            rule,
+            firsts,
+            mut stats,
+            mut budgets,
+        } = opts;
+        if let Ok(v) = self.find_node(&data.proxy_host).await {
+            stats.push(v.traffic().clone());
+        }
+        budgets.push(self.node_budget(&data.proxy_host));
+        budgets.push(self.inner.budget.clone());
+        let traffic = TrafficStat::new();
+        stats.push(traffic.clone());
+        let gotos = format!("{}:{}", data.proxy_host.as_str(), data.proxy_port);
+        let client = conn.peer_addr();
         let px = Proxyer::new(
             self.inner.ctx.clone(),
             ProxyerCfg {
-                ids: format!("{}:{}", data.proxy_host.as_str(), data.proxy_port),
+                ids: gotos.clone(),
                 limit: data.limit.clone(),
+                stats,
+                budgets,
             },
             conn,
             connlc,
         );
+        if let Some(bts) = firsts {
+            px.push_firsts(bts).await;
+        }
+        let id = self.inner.conns.add(&px, rule, gotos, client, traffic);
         px.start().await;
+        self.inner.conns.remove(id.as_str());
+    }
+    pub fn conn_list(&self, rule: Option<&str>) -> Vec<ConnListIt> {
+        self.inner.conns.list(rule)
+    }
+    pub fn conn_kill(&self, id: &str) -> bool {
+        self.inner.conns.kill(id)
+    }
+
+    /// the server-wide budget
+    pub fn budget(&self) -> &Arc<Budget> {
+        &self.inner.budget
+    }
+    pub fn node_budget(&self, name: &str) -> Arc<Budget> {
+        let mut lkv = match self.inner.node_budgets.lock() {
+            Err(_) => return Budget::new(None),
+            Ok(v) => v,
+        };
+        lkv.entry(name.to_string())
+            .or_insert_with(|| Budget::new(None))
+            .clone()
+    }
+    /// the conf budget of a node,a budget set by the api before is kept
+    pub fn node_budget_init(&self, name: &String, limit: Option<ProxyLimit>) {
+        if let Ok(mut lkv) = self.inner.node_budgets.lock() {
+            if !lkv.contains_key(name) {
+                lkv.insert(name.clone(), Budget::new(limit));
+            }
+        }
+    }
+    /// the nodes with a limit
+    pub fn node_budgets(&self) -> Vec<(String, ProxyLimit)> {
+        let mut rts = Vec::new();
+        if let Ok(lkv) = self.inner.node_budgets.lock() {
+            for (k, v) in lkv.iter() {
+                if let Some(lmt) = v.limit() {
+                    rts.push((k.clone(), lmt));
+                }
+            }
+        }
+        rts.sort_by(|a, b| a.0.cmp(&b.0));
+        rts
     }
 }
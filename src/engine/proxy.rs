@@ -1,6 +1,15 @@
-use std::{collections::HashMap, io, path::Path, time::Duration};
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-use async_std::{sync::RwLock, task};
+use async_std::{
+    channel::{self, Receiver},
+    sync::{Mutex, RwLock},
+    task,
+};
 use ruisutil::ArcMut;
 
 use crate::{
@@ -8,12 +17,15 @@ use crate::{
     entity::{
         conf::ProxyInfoConf,
         node::ProxyGoto,
-        proxy::{ProxyListIt, ProxyListRep},
+        proxy::{ProxyListIt, ProxyListRep, ProxyReloadRep},
     },
     utils,
 };
 
-use super::{rule::RuleProxy, NodeEngine, RuleCfg};
+use super::{balance::BALANCES, rule::RuleProxy, watch, NodeEngine, RuleCfg};
+
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+const WATCH_DEBOUNCE_MAX: Duration = Duration::from_secs(5);
 
 #[derive(Clone)]
 pub struct ProxyEngine {
@@ -23,6 +35,7 @@ struct Inner {
     ctx: ruisutil::Context,
     node: NodeEngine,
     proxys: RwLock<HashMap<String, RuleProxy>>,
+    reloading: Mutex<()>,
 }
 
 impl ProxyEngine {
@@ -32,42 +45,14 @@ impl ProxyEngine {
                 ctx: ruisutil::Context::background(Some(ctx)),
                 node: node,
                 proxys: RwLock::new(HashMap::new()),
+                reloading: Mutex::new(()),
             }),
         }
     }
 
-    pub async fn wait_proxys_clear(&self) {
-        while !self.inner.ctx.done() {
-            task::sleep(Duration::from_millis(500)).await;
-            let lkv = self.inner.proxys.read().await;
-            if lkv.len() <= 0 {
-                return;
-            }
-            let mut alled = true;
-            for (k, v) in lkv.iter() {
-                if !v.stopd() {
-                    log::debug!("{} not stop!!!!!!", k.as_str());
-                    alled = false;
-                }
-            }
-            if alled {
-                break;
-            }
-        }
-        {
-            let mut lkv = self.inner.proxys.write().await;
-            lkv.clear();
-        }
-    }
-    pub async fn reload(&self) -> io::Result<()> {
+    pub async fn reload(&self) -> io::Result<ProxyReloadRep> {
         log::debug!("proxy reload start");
-        let path = match &Application::get().conf {
-            None => "/etc/hbproxy/proxys".to_string(),
-            Some(v) => match &v.server.proxys_path {
-                None => "/etc/hbproxy/proxys".to_string(),
-                Some(v) => v.clone(),
-            },
-        };
+        let path = proxys_path();
         let pth = Path::new(path.as_str());
         if !pth.exists() || !pth.is_dir() {
             return Err(ruisutil::ioerr(
@@ -81,106 +66,155 @@ impl ProxyEngine {
                 None,
             ));
         }
-        {
-            let lkv = self.inner.proxys.read().await;
-            for (_, v) in lkv.iter() {
-                v.stop();
-            }
-        }
-        self.wait_proxys_clear().await;
-
+        let _lk = self.inner.reloading.lock().await;
+        let mut rts = ProxyReloadRep::default();
+        let mut news: Vec<LoadRule> = Vec::new();
+        let mut bads: Vec<Option<String>> = Vec::new();
         for e in std::fs::read_dir(pth)? {
             let dir = e?;
             let dpth = dir.path();
-            let dpths = if let Some(vs) = dpth.to_str() {
-                vs
-            } else {
-                "xxx"
-            };
-            if dpth.is_file() {
-                match self.load_confs(&dpth).await {
-                    Err(e) => log::error!("load conf({}) faild:{}", dpths, e),
-                    Ok(_) => log::info!("load conf({}) success", dpths),
+            // a save in progress
+            if !dpth.is_file() || dpth.extension() == Some(std::ffi::OsStr::new("tmp")) {
+                continue;
+            }
+            let files = dpth.to_str().map(|v| v.to_string());
+            let dpths = files.clone().unwrap_or("xxx".to_string());
+            match load_confs(&dpth, &files) {
+                Err(e) => {
+                    log::error!("load conf({}) faild:{}", dpths.as_str(), e);
+                    rts.errs.push(format!("{}:{}", dpths.as_str(), e));
+                    bads.push(files);
+                }
+                Ok(vs) => {
+                    log::info!("load conf({}) success", dpths.as_str());
+                    news.extend(vs);
                 }
             }
         }
 
-        Ok(())
-    }
-
-    async fn load_confs(&self, dpth: &Path) -> io::Result<()> {
-        let rs: io::Result<Vec<ProxyInfoConf>> = utils::ymlfile(&dpth);
-        match rs {
-            Err(e) => log::warn!("load confs faild:{}", e),
-            Ok(vs) => {
-                for v in vs {
-                    self.load_conf(v).await?;
-                }
-                return Ok(());
+        let olds = self.inner.proxys.read().await.clone();
+        // unnamed rules keep the name they got at the last load
+        for v in news.iter_mut() {
+            if v.named || v.cfg.bind_port == 0 {
+                continue;
+            }
+            let cfg = &mut v.cfg;
+            let old = olds
+                .iter()
+                .find(|(_, v)| v.conf().file == cfg.file && v.conf().bind_port == cfg.bind_port);
+            if let Some((k, _)) = old {
+                cfg.name = k.clone();
             }
         }
-        let rs: io::Result<ProxyInfoConf> = utils::ymlfile(&dpth);
-        match rs {
-            Err(e) => log::warn!("load conf faild:{}", e),
-            Ok(v) => {
-                self.load_conf(v).await?;
-                return Ok(());
+        let mut nms: HashMap<String, usize> = HashMap::new();
+        for (i, v) in news.iter().enumerate() {
+            if nms.contains_key(&v.cfg.name) {
+                rts.errs.push(format!("proxy name is exsit:{}", v.cfg.name.as_str()));
+            } else {
+                nms.insert(v.cfg.name.clone(), i);
             }
         }
-        Err(ruisutil::ioerr("conf yml err", None))
-    }
-    async fn load_conf(&self, cfg: ProxyInfoConf) -> io::Result<()> {
-        /* let cfg: ProxyInfoConf = match utils::ymlfile(&dpth) {
-            Err(e) => return Err(ruisutil::ioerr(format!("ymlfile err:{}", e), None)),
-            Ok(v) => v,
-        }; */
-        let bindls: Vec<&str> = cfg.bind.split(":").collect();
-        if bindls.len() != 2 {
-            return Err(ruisutil::ioerr("bind len err", None));
+
+        // old listeners are closed before any rule binds,a port may move between rules
+        let mut stops = Vec::new();
+        for (k, v) in olds.iter() {
+            if !nms.contains_key(k) && !bads.contains(&v.conf().file) {
+                v.stop();
+                stops.push(v.clone());
+                self.inner.proxys.write().await.remove(k);
+                rts.removed.push(k.clone());
+            }
         }
-        let bindport = if let Ok(v) = bindls[1].parse::<i32>() {
-            if v <= 0 {
-                return Err(ruisutil::ioerr("bind port err:<=0", None));
+        let mut adds = Vec::new();
+        for (k, i) in nms.iter() {
+            let it = &news[*i];
+            match olds.get(k) {
+                None => {
+                    adds.push(*i);
+                    rts.added.push(k.clone());
+                }
+                Some(v) if same_rule(v, &it.cfg) => {
+                    if it.stop == v.stopd() {
+                        continue;
+                    }
+                    if it.stop {
+                        v.stop();
+                    } else if let Err(e) = v.start(self.inner.ctx.clone()).await {
+                        rts.errs.push(format!("{}:{}", k.as_str(), e));
+                    }
+                    rts.changed.push(k.clone());
+                }
+                Some(v) => {
+                    v.stop();
+                    stops.push(v.clone());
+                    adds.push(*i);
+                    rts.changed.push(k.clone());
+                }
             }
-            v
-        } else {
-            return Err(ruisutil::ioerr("bind port err", None));
-        };
-        /* let gotols: Vec<&str> = cfg.proxy.split(":").collect();
-        if gotols.len() != 2 {
-            return Err(ruisutil::ioerr("goto len err", None));
         }
-        let gotoport = if let Ok(v) = gotols[1].parse::<i32>() {
-            if v <= 0 {
-                return Err(ruisutil::ioerr("goto port err:<=0", None));
+        for v in &stops {
+            v.wait_closed().await;
+        }
+        for (i, v) in news.into_iter().enumerate() {
+            if !adds.contains(&i) {
+                continue;
+            }
+            let nms = v.cfg.name.clone();
+            if let Err(e) = self.add_proxy(v.cfg, v.stop).await {
+                log::error!("reload add {} err:{}", nms.as_str(), e);
+                rts.errs.push(format!("{}:{}", nms.as_str(), e));
             }
-            v
-        } else {
-            return Err(ruisutil::ioerr("goto port err", None));
-        }; */
-        let data = RuleCfg {
-            name: match &cfg.name {
-                None => format!("b{}{}", bindport, ruisutil::random(5).as_str()),
-                Some(vs) => vs.clone(),
-            },
-            bind_host: if bindls[0].is_empty() {
-                "0.0.0.0".to_string()
-            } else {
-                bindls[0].to_string()
-            },
-            bind_port: bindport,
-            goto: cfg.convs_proxy_goto()?,
-        };
-        match self.add_check(&data).await {
-            0 => {}
-            1 => return Err(ruisutil::ioerr("proxy name is exsit", None)),
-            2 => return Err(ruisutil::ioerr("proxy port is exsit", None)),
-            _ => return Err(ruisutil::ioerr("add check err", None)),
         }
-        let stopd = if let Some(v) = &cfg.stop { *v } else { false };
-        self.add_proxy(data, stopd).await?;
+        Ok(rts)
+    }
+
+    /// reload when conf files in proxys_path change,a burst of changes is applied once
+    pub fn watch(&self) -> io::Result<()> {
+        let (sdr, rcv) = channel::bounded(1024);
+        watch::watch_dir(proxys_path().as_str(), sdr)?;
+        let c = self.clone();
+        task::spawn(async move {
+            c.run_watch(rcv).await;
+        });
         Ok(())
     }
+    async fn run_watch(&self, rcv: Receiver<String>) {
+        while !self.inner.ctx.done() {
+            let name = match async_std::future::timeout(Duration::from_secs(1), rcv.recv()).await {
+                Err(_) => continue,
+                Ok(Err(_)) => break,
+                Ok(Ok(v)) => v,
+            };
+            let mut names = vec![name];
+            let start = Instant::now();
+            while start.elapsed() < WATCH_DEBOUNCE_MAX {
+                match async_std::future::timeout(WATCH_DEBOUNCE, rcv.recv()).await {
+                    Ok(Ok(v)) => {
+                        if !names.contains(&v) {
+                            names.push(v);
+                        }
+                    }
+                    _ => break,
+                }
+            }
+            log::info!("proxys changed:{}", names.join(","));
+            match self.reload().await {
+                Err(e) => log::error!("watch reload err:{}", e),
+                Ok(v) => {
+                    log::info!(
+                        "watch reload added:[{}],removed:[{}],changed:[{}]",
+                        v.added.join(","),
+                        v.removed.join(","),
+                        v.changed.join(",")
+                    );
+                    for e in &v.errs {
+                        log::error!("watch reload err:{}", e);
+                    }
+                }
+            }
+        }
+        rcv.close();
+    }
 
     pub async fn add_check(&self, cfg: &RuleCfg) -> i8 {
         let lkv = self.inner.proxys.read().await;
@@ -196,11 +230,18 @@ impl ProxyEngine {
         }
         0
     }
-    pub async fn add_proxy(&self, cfg: RuleCfg, stopd: bool) -> io::Result<RuleProxy> {
+    pub async fn add_proxy(&self, mut cfg: RuleCfg, stopd: bool) -> io::Result<RuleProxy> {
         let nms = cfg.name.clone();
         if nms.is_empty() {
             return Err(ruisutil::ioerr("name is empty!", None));
         }
+        if cfg.file.is_none() {
+            // a replaced rule stays in its file,a new one gets its own
+            cfg.file = match self.get_rule(&nms).await {
+                Some(v) if v.conf().file.is_some() => v.conf().file.clone(),
+                _ => Some(self.new_rule_file(&nms).await?),
+            };
+        }
         if let Err(e) = self.stop(&nms).await {
             log::debug!("add {} stop err:{}", nms.as_str(), e);
         }
@@ -213,21 +254,53 @@ impl ProxyEngine {
         Ok(proxy)
     }
 
+    // a file no rule was loaded from is not ours to write,even if it has the name
+    async fn new_rule_file(&self, name: &str) -> io::Result<String> {
+        let dpth = rule_file(name);
+        let files = match dpth.to_str() {
+            None => return Err(ruisutil::ioerr("conf file path err", None)),
+            Some(v) => v.to_string(),
+        };
+        let lkv = self.inner.proxys.read().await;
+        if dpth.exists() || lkv.values().any(|v| v.conf().file.as_deref() == Some(files.as_str())) {
+            return Err(ruisutil::ioerr(format!("conf file {} is in use", files), None));
+        }
+        Ok(files)
+    }
+
+    pub async fn get_rule(&self, name: &String) -> Option<RuleProxy> {
+        let lkv = self.inner.proxys.read().await;
+        lkv.get(name).cloned()
+    }
+
+    pub async fn rules(&self) -> Vec<RuleProxy> {
+        let lkv = self.inner.proxys.read().await;
+        lkv.values().cloned().collect()
+    }
+
     pub async fn get_info(&self, name: &String) -> Option<ProxyListIt> {
         //let mut rts = NodeListIt {  };
         let lkv = self.inner.proxys.read().await;
         let v = lkv.get(name)?;
+        let (allow, deny) = v.acl_lists();
         Some(ProxyListIt {
             name: v.conf().name.clone(),
             remote: format!("{}:{}", v.conf().bind_host.as_str(), v.conf().bind_port),
+            protocol: Some(v.conf().protocol.clone()),
+            mode: Some(v.conf().mode.clone()),
             /* proxy: format!(
                 "{}:{}",
                 v.conf().goto.proxy_host.as_str(),
                 v.conf().goto.proxy_port
             ), */
             goto: v.conf().goto.clone(),
+            routes: Some(v.conf().routes.clone()),
             status: v.status(),
             msg: v.msg(),
+            health: v.health(),
+            traffic: Some(v.traffic()),
+            allow: Some(allow).filter(|v| !v.is_empty()),
+            deny: Some(deny).filter(|v| !v.is_empty()),
         })
     }
 
@@ -236,17 +309,25 @@ impl ProxyEngine {
         let lkv = self.inner.proxys.read().await;
         for (_, v) in lkv.iter() {
             // v.conf().name
+            let (allow, deny) = v.acl_lists();
             rts.list.push(ProxyListIt {
                 name: v.conf().name.clone(),
                 remote: format!("{}:{}", v.conf().bind_host.as_str(), v.conf().bind_port),
+                protocol: Some(v.conf().protocol.clone()),
+                mode: Some(v.conf().mode.clone()),
                 /* proxy: format!(
                     "{}:{}",
                     v.conf().goto.proxy_host.as_str(),
                     v.conf().goto.proxy_port
                 ), */
                 goto: v.conf().goto.clone(),
+                routes: Some(v.conf().routes.clone()),
                 status: v.status(),
                 msg: v.msg(),
+                health: v.health(),
+                traffic: Some(v.traffic()),
+                allow: Some(allow).filter(|v| !v.is_empty()),
+                deny: Some(deny).filter(|v| !v.is_empty()),
             });
         }
         Ok(rts)
@@ -271,11 +352,226 @@ impl ProxyEngine {
             Err(ruisutil::ioerr("not found proxy", None))
         }
     }
-    pub async fn remove(&self, name: &String) {
+    pub async fn remove(&self, name: &String) -> io::Result<()> {
         let mut lkv = self.inner.proxys.write().await;
         if let Some(v) = lkv.remove(name) {
             v.stop();
             log::debug!("proxy remove:{}!!!!", name.as_str());
+            if let Some(file) = &v.conf().file {
+                save_rule(Path::new(file), v.conf(), None)?;
+            }
         }
+        Ok(())
     }
+    /// write the rule with its start/stop state back to its conf file
+    pub async fn persist(&self, name: &String) -> io::Result<()> {
+        let lkv = self.inner.proxys.read().await;
+        let v = match lkv.get(name) {
+            None => return Err(ruisutil::ioerr("not found proxy", None)),
+            Some(v) => v,
+        };
+        match &v.conf().file {
+            None => Err(ruisutil::ioerr("proxy has no conf file", None)),
+            Some(file) => save_rule(Path::new(file), v.conf(), Some(v.conv_conf(v.stopd()))),
+        }
+    }
+}
+
+fn proxys_path() -> String {
+    match &Application::get().conf {
+        None => "/etc/hbproxy/proxys".to_string(),
+        Some(v) => match &v.server.proxys_path {
+            None => "/etc/hbproxy/proxys".to_string(),
+            Some(v) => v.clone(),
+        },
+    }
+}
+// the api rules are kept apart from the hand written files
+fn rule_file(name: &str) -> PathBuf {
+    let nms: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    Path::new(proxys_path().as_str()).join(format!("api-{}.yml", nms))
+}
+
+// a conf file holds a list of rules or a single one
+fn read_confs(dpth: &Path) -> io::Result<Vec<ProxyInfoConf>> {
+    let rs: io::Result<Vec<ProxyInfoConf>> = utils::ymlfile(dpth);
+    match rs {
+        Err(e) => log::warn!("load confs faild:{}", e),
+        Ok(vs) => return Ok(vs),
+    }
+    let rs: io::Result<ProxyInfoConf> = utils::ymlfile(dpth);
+    match rs {
+        Err(e) => log::warn!("load conf faild:{}", e),
+        Ok(v) => return Ok(vec![v]),
+    }
+    Err(ruisutil::ioerr("conf yml err", None))
+}
+struct LoadRule {
+    cfg: RuleCfg,
+    stop: bool,
+    named: bool,
+}
+// all rules of a conf file,or err if any of them is bad
+fn load_confs(dpth: &Path, file: &Option<String>) -> io::Result<Vec<LoadRule>> {
+    let mut rts = Vec::new();
+    for v in read_confs(dpth)? {
+        let cfg = match conv_rule(&v, file.clone()) {
+            Err(e) => {
+                let nms = v.name.as_deref().unwrap_or(v.bind.as_str()).to_string();
+                return Err(ruisutil::ioerr(format!("rule {}:{}", nms, e), None));
+            }
+            Ok(v) => v,
+        };
+        rts.push(LoadRule {
+            cfg,
+            stop: v.stop.unwrap_or(false),
+            named: v.name.is_some(),
+        });
+    }
+    Ok(rts)
+}
+fn same_rule(a: &RuleProxy, b: &RuleCfg) -> bool {
+    if a.conf().file != b.file {
+        return false;
+    }
+    match (
+        serde_json::to_string(&a.conv_conf(false)),
+        serde_json::to_string(&b.conv_conf(false)),
+    ) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+/// replace(or remove if data is None) the rule in its conf file,the other rules in it are kept.
+fn save_rule(file: &Path, cfg: &RuleCfg, data: Option<ProxyInfoConf>) -> io::Result<()> {
+    let mut ls = if file.exists() {
+        read_confs(file)?
+    } else {
+        if let Some(dir) = file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        Vec::new()
+    };
+    // rules without a name got a random one when loaded,they are known by the bind port
+    let pos = ls.iter().position(|v| match &v.name {
+        Some(vs) => vs == &cfg.name,
+        None => {
+            cfg.bind_port != 0
+                && v.bind.rsplit(':').next() == Some(cfg.bind_port.to_string().as_str())
+        }
+    });
+    match (pos, data) {
+        (Some(i), Some(v)) => ls[i] = v,
+        (Some(i), None) => {
+            ls.remove(i);
+        }
+        (None, Some(v)) => ls.push(v),
+        (None, None) => return Ok(()),
+    }
+    match ls.len() {
+        0 => std::fs::remove_file(file),
+        1 => utils::ymlfile_save(file, &ls[0]),
+        _ => utils::ymlfile_save(file, &ls),
+    }
+}
+
+/// tcp when not set,http and tls-sni route tcp conns only
+pub fn conv_mode(mode: Option<&str>, protocol: &str) -> io::Result<String> {
+    match mode {
+        None | Some("tcp") => Ok("tcp".to_string()),
+        Some(vs) if protocol == "tcp" && (vs == "http" || vs == "tls-sni") => Ok(vs.to_string()),
+        Some(vs) => Err(ruisutil::ioerr(format!("mode err:{}", vs), None)),
+    }
+}
+/// failover when not set
+pub fn conv_balance(balance: Option<&str>) -> io::Result<String> {
+    match balance {
+        None => Ok("failover".to_string()),
+        Some(vs) if BALANCES.contains(&vs) => Ok(vs.to_string()),
+        Some(vs) => Err(ruisutil::ioerr(format!("balance err:{}", vs), None)),
+    }
+}
+
+fn conv_rule(cfg: &ProxyInfoConf, file: Option<String>) -> io::Result<RuleCfg> {
+    /* let cfg: ProxyInfoConf = match utils::ymlfile(&dpth) {
+        Err(e) => return Err(ruisutil::ioerr(format!("ymlfile err:{}", e), None)),
+        Ok(v) => v,
+    }; */
+    let bindls: Vec<&str> = if cfg.bind.is_empty() {
+        vec!["", "0"]
+    } else {
+        cfg.bind.split(":").collect()
+    };
+    if bindls.len() != 2 {
+        return Err(ruisutil::ioerr("bind len err", None));
+    }
+    let bindport = if let Ok(v) = bindls[1].parse::<i32>() {
+        if v < 0 || (v == 0 && !cfg.bind.is_empty()) {
+            return Err(ruisutil::ioerr("bind port err:<=0", None));
+        }
+        v
+    } else {
+        return Err(ruisutil::ioerr("bind port err", None));
+    };
+    /* let gotols: Vec<&str> = cfg.proxy.split(":").collect();
+    if gotols.len() != 2 {
+        return Err(ruisutil::ioerr("goto len err", None));
+    }
+    let gotoport = if let Ok(v) = gotols[1].parse::<i32>() {
+        if v <= 0 {
+            return Err(ruisutil::ioerr("goto port err:<=0", None));
+        }
+        v
+    } else {
+        return Err(ruisutil::ioerr("goto port err", None));
+    }; */
+    let protocol = match &cfg.protocol {
+        None => "tcp".to_string(),
+        Some(vs) => match vs.as_str() {
+            "tcp" | "udp" => vs.clone(),
+            _ => return Err(ruisutil::ioerr(format!("protocol err:{}", vs), None)),
+        },
+    };
+    let mode = conv_mode(cfg.mode.as_deref(), protocol.as_str())?;
+    let balance = conv_balance(cfg.balance.as_deref())?;
+    let mut routes = Vec::new();
+    if let Some(vs) = &cfg.routes {
+        for v in vs {
+            routes.push(v.conv_route()?);
+        }
+    }
+    let data = RuleCfg {
+        name: match &cfg.name {
+            None => format!("b{}{}", bindport, ruisutil::random(5).as_str()),
+            Some(vs) => vs.clone(),
+        },
+        protocol,
+        bind_host: if bindls[0].is_empty() {
+            "0.0.0.0".to_string()
+        } else {
+            bindls[0].to_string()
+        },
+        bind_port: bindport,
+        goto: cfg.convs_proxy_goto()?,
+        visit_key: cfg.visit_key.clone(),
+        mode,
+        routes,
+        balance,
+        health: cfg.health_check.unwrap_or(false),
+        budget: cfg.budget.clone(),
+        max_conns: cfg.max_conns.unwrap_or(0),
+        max_conns_per_ip: cfg.max_conns_per_ip.unwrap_or(0),
+        conn_rate: cfg.conn_rate.unwrap_or(0),
+        allow: cfg.allow.clone().unwrap_or_default(),
+        deny: cfg.deny.clone().unwrap_or_default(),
+        file,
+    };
+    utils::IpAcl::parse(&data.allow, &data.deny)?;
+    Ok(data)
 }
@@ -0,0 +1,114 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+use crate::entity::node::ProxyProtoMsg;
+
+const V2_SIG: [u8; 12] = [0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A];
+
+// a v4 client on a dual-stack listener shows as ::ffff:a.b.c.d
+fn unmap(v: SocketAddr) -> SocketAddr {
+    match v.ip() {
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            None => v,
+            Some(ip4) => SocketAddr::new(IpAddr::V4(ip4), v.port()),
+        },
+        IpAddr::V4(_) => v,
+    }
+}
+fn addrs(msg: &ProxyProtoMsg) -> Option<(SocketAddr, SocketAddr)> {
+    let src: SocketAddr = msg.src.as_ref()?.parse().ok()?;
+    let dst: SocketAddr = msg.dst.as_ref()?.parse().ok()?;
+    let (src, dst) = (unmap(src), unmap(dst));
+    if src.is_ipv4() != dst.is_ipv4() {
+        return None;
+    }
+    Some((src, dst))
+}
+
+/// the header a node writes to the local conn before any byte of the client,
+/// a conn without both addrs(a visitor) gets UNKNOWN/LOCAL.
+pub fn header(msg: &ProxyProtoMsg) -> io::Result<Vec<u8>> {
+    let addrs = addrs(msg);
+    if msg.version != "v1" && msg.version != "v2" {
+        return Err(ruisutil::ioerr(format!("proxy_protocol err:{}", msg.version), None));
+    }
+    if msg.version == "v1" {
+        let rts = match addrs {
+            None => "PROXY UNKNOWN\r\n".to_string(),
+            Some((src, dst)) => format!(
+                "PROXY {} {} {} {} {}\r\n",
+                if src.is_ipv4() { "TCP4" } else { "TCP6" },
+                src.ip(),
+                dst.ip(),
+                src.port(),
+                dst.port()
+            ),
+        };
+        return Ok(rts.into_bytes());
+    }
+    let mut rts = V2_SIG.to_vec();
+    match addrs {
+        None => rts.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]),
+        Some((src, dst)) => {
+            rts.push(0x21);
+            match (src.ip(), dst.ip()) {
+                (IpAddr::V4(s), IpAddr::V4(d)) => {
+                    rts.extend_from_slice(&[0x11, 0x00, 12]);
+                    rts.extend_from_slice(&s.octets());
+                    rts.extend_from_slice(&d.octets());
+                }
+                (IpAddr::V6(s), IpAddr::V6(d)) => {
+                    rts.extend_from_slice(&[0x21, 0x00, 36]);
+                    rts.extend_from_slice(&s.octets());
+                    rts.extend_from_slice(&d.octets());
+                }
+                _ => return Err(ruisutil::ioerr("proxy_protocol addr family err", None)),
+            }
+            rts.extend_from_slice(&src.port().to_be_bytes());
+            rts.extend_from_slice(&dst.port().to_be_bytes());
+        }
+    }
+    Ok(rts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{header, V2_SIG};
+    use crate::entity::node::ProxyProtoMsg;
+
+    fn msg(version: &str, src: Option<&str>, dst: Option<&str>) -> ProxyProtoMsg {
+        ProxyProtoMsg {
+            version: version.to_string(),
+            src: src.map(|v| v.to_string()),
+            dst: dst.map(|v| v.to_string()),
+        }
+    }
+
+    #[test]
+    fn headers() {
+        let m = msg("v1", Some("1.2.3.4:5678"), Some("10.0.0.1:443"));
+        assert_eq!(header(&m).unwrap(), b"PROXY TCP4 1.2.3.4 10.0.0.1 5678 443\r\n");
+        let m = msg("v1", Some("[::ffff:1.2.3.4]:5678"), Some("10.0.0.1:443"));
+        assert_eq!(header(&m).unwrap(), b"PROXY TCP4 1.2.3.4 10.0.0.1 5678 443\r\n");
+        let m = msg("v1", Some("[fd00::1]:5678"), Some("[fd00::2]:22"));
+        assert_eq!(header(&m).unwrap(), b"PROXY TCP6 fd00::1 fd00::2 5678 22\r\n");
+        let m = msg("v1", None, None);
+        assert_eq!(header(&m).unwrap(), b"PROXY UNKNOWN\r\n");
+
+        let m = msg("v2", Some("1.2.3.4:5678"), Some("10.0.0.1:443"));
+        let mut want = V2_SIG.to_vec();
+        want.extend_from_slice(&[0x21, 0x11, 0x00, 12, 1, 2, 3, 4, 10, 0, 0, 1]);
+        want.extend_from_slice(&[0x16, 0x2E, 0x01, 0xBB]);
+        assert_eq!(header(&m).unwrap(), want);
+        let m = msg("v2", Some("[fd00::1]:5678"), Some("[fd00::2]:22"));
+        let bts = header(&m).unwrap();
+        assert_eq!(bts.len(), 16 + 36);
+        assert_eq!(&bts[12..16], &[0x21, 0x21, 0x00, 36]);
+        let m = msg("v2", Some("1.2.3.4:5678"), None);
+        let mut want = V2_SIG.to_vec();
+        want.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        assert_eq!(header(&m).unwrap(), want);
+    }
+}
@@ -1,19 +1,26 @@
-use std::{
-    io,
-    net::Shutdown,
-    sync::Arc,
-    time::{Duration, SystemTime},
-};
+use std::{io, net::Shutdown, sync::Arc};
 
-use async_std::{net::TcpStream, sync::RwLock, task};
-use futures::AsyncReadExt;
+use async_std::{sync::RwLock, task};
 use ruisutil::{bytes::ByteBoxBuf, ArcMut};
 
-use crate::entity::util::ProxyLimit;
+use crate::{
+    entity::util::ProxyLimit,
+    utils::{Notify, NOTIFY_RECHECK},
+};
+
+use super::{
+    conn::ProxyConn,
+    limiter::{self, Bucket, Budget},
+    stats::{TrafficConn, TrafficStat},
+};
 
 pub struct ProxyerCfg {
     pub ids: String,
     pub limit: Option<ProxyLimit>,
+    // the rule and node stats the bytes are counted on
+    pub stats: Vec<Arc<TrafficStat>>,
+    // the shared rule,node and server budgets
+    pub budgets: Vec<Arc<Budget>>,
 }
 
 #[derive(Clone)]
@@ -23,12 +30,20 @@ pub struct Proxyer {
 struct Inner {
     ctx: ruisutil::Context,
     cfg: ProxyerCfg,
-    conn: TcpStream,
-    connlc: TcpStream,
+    conn: ProxyConn,
+    connlc: ProxyConn,
 
     bufw: RwLock<ByteBoxBuf>,
     buflcw: RwLock<ByteBoxBuf>,
-    speedtm: Duration,
+    // data or a read end for the writers,room under PROXY_BUF_SIZE_MAX for the readers
+    notw: Notify,
+    notlcw: Notify,
+    roomw: Notify,
+    roomlcw: Notify,
+    // limit.up on conn->connlc,limit.down on connlc->conn
+    up: Bucket,
+    down: Bucket,
+    traffic: TrafficConn,
 
     endr1: bool,
     endr2: bool,
@@ -38,9 +53,11 @@ impl Proxyer {
     pub fn new(
         ctx: ruisutil::Context,
         cfg: ProxyerCfg,
-        conn: TcpStream,
-        connlc: TcpStream,
+        conn: ProxyConn,
+        connlc: ProxyConn,
     ) -> Self {
+        let traffic = TrafficConn::new(cfg.stats.clone());
+        let (up, down) = limiter::buckets(&cfg.limit);
         Self {
             inner: ArcMut::new(Inner {
                 ctx: ruisutil::Context::background(Some(ctx)),
@@ -50,7 +67,13 @@ impl Proxyer {
 
                 bufw: RwLock::new(ByteBoxBuf::new()),
                 buflcw: RwLock::new(ByteBoxBuf::new()),
-                speedtm: Duration::from_millis(500),
+                notw: Notify::new(),
+                notlcw: Notify::new(),
+                roomw: Notify::new(),
+                roomlcw: Notify::new(),
+                up,
+                down,
+                traffic,
 
                 endr1: false,
                 endr2: false,
@@ -58,6 +81,14 @@ impl Proxyer {
         }
     }
 
+    pub async fn push_firsts(&self, bts: Box<[u8]>) {
+        let n = bts.len();
+        self.inner.traffic.up(n);
+        let mut lkv = self.inner.buflcw.write().await;
+        lkv.pushs(Arc::new(bts), 0, n);
+        self.inner.notlcw.notify();
+    }
+
     fn closer(&self) {
         if let Err(e) = self.inner.conn.shutdown(Shutdown::Read) {
             log::debug!("closer err:{}", e);
@@ -68,8 +99,12 @@ impl Proxyer {
             log::debug!("closelcr err:{}", e);
         }
     }
-    fn stop(&self) {
+    pub fn stop(&self) {
         self.inner.ctx.stop();
+        self.inner.notw.close();
+        self.inner.notlcw.close();
+        self.inner.roomw.close();
+        self.inner.roomlcw.close();
         if let Err(e) = self.inner.conn.shutdown(Shutdown::Both) {
             log::debug!("stop conn.shutdown err:{}", e);
         }
@@ -89,6 +124,7 @@ impl Proxyer {
             }
             // c.closer();
             unsafe { c.inner.muts().endr1 = true };
+            c.inner.notlcw.notify();
             std::mem::drop(wgc);
             log::debug!(
                 "Proxyer({}) read1 end!byte count:{}",
@@ -121,6 +157,7 @@ impl Proxyer {
             }
             // c.closer();
             unsafe { c.inner.muts().endr2 = true };
+            c.inner.notw.notify();
             std::mem::drop(wgc);
             log::debug!(
                 "Proxyer({}) read2 end!byte count:{}",
@@ -150,25 +187,18 @@ impl Proxyer {
     }
     async fn max_wait(&self, fs: i8) {
         while !self.inner.ctx.done() {
-            let ln = if fs == 1 {
-                self.inner.buflcw.read().await.len()
+            let (ln, room) = if fs == 1 {
+                (self.inner.buflcw.read().await.len(), &self.inner.roomlcw)
             } else {
-                self.inner.bufw.read().await.len()
+                (self.inner.bufw.read().await.len(), &self.inner.roomw)
             };
             if ln <= PROXY_BUF_SIZE_MAX {
                 break;
             }
-            task::sleep(Duration::from_millis(2)).await;
+            room.wait(NOTIFY_RECHECK).await;
         }
     }
     pub async fn read1(&self, count: &mut usize) -> io::Result<()> {
-        let mut ts = SystemTime::now();
-        let mut ln = 0;
-        let lmt = if let Some(lmt) = &self.inner.cfg.limit {
-            Some(lmt.up * 1024 / 2)
-        } else {
-            None
-        };
         let ins = unsafe { self.inner.muts() };
         while !self.inner.ctx.done() {
             let mut buf: Box<[u8]> = vec![0u8; 10240].into_boxed_slice();
@@ -182,82 +212,28 @@ impl Proxyer {
                 lkv.pushs(Arc::new(buf), 0, n);
                 *count += n;
             }
-            if let Some(lmv) = lmt {
-                ln += n;
-                if lmv > 0 && ln >= lmv {
-                    if let Ok(t) = SystemTime::now().duration_since(ts) {
-                        if t < self.inner.speedtm {
-                            let wt = self.inner.speedtm - t;
-                            log::debug!(
-                                "read1 limit({}b/500ms) down ({}) uses:{}ms, waits:{}ms",
-                                lmv,
-                                ln,
-                                t.as_millis(),
-                                wt.as_millis()
-                            );
-                            task::sleep(wt).await;
-                        }
-                    };
-                    ts = SystemTime::now();
-                    ln = 0;
-                }
-            }
+            self.inner.notlcw.notify();
+            self.inner.traffic.up(n);
+            limiter::waits_up(&self.inner.up, &self.inner.cfg.budgets, n).await;
         }
         Ok(())
     }
 
     pub async fn write1(&self, count: &mut usize) -> io::Result<()> {
-        let mut ts = SystemTime::now();
-        let mut ln = 0;
-        let lmt = if let Some(lmt) = &self.inner.cfg.limit {
-            Some(lmt.down * 1024 / 2)
-        } else {
-            None
-        };
         let ins = unsafe { self.inner.muts() };
         while !self.inner.ctx.done() {
-            {
-                let lkv = self.inner.bufw.read().await;
-                if lkv.len() <= 0 {
-                    if self.inner.endr2 {
-                        break;
-                    }
-                    task::sleep(Duration::from_millis(2)).await;
-                    continue;
-                }
-            }
             let bts = {
                 let mut lkv = self.inner.bufw.write().await;
                 lkv.pull()
             };
             if let Some(v) = bts {
-                ruisutil::tcp_write_async(&self.inner.ctx, &mut ins.conn, &v).await?;
+                self.inner.roomw.notify();
+                ins.conn.write_all(&self.inner.ctx, &v).await?;
                 *count += v.len();
-
-                if let Some(lmv) = lmt {
-                    ln += v.len();
-                    if lmv > 0 && ln >= lmv {
-                        if let Ok(t) = SystemTime::now().duration_since(ts) {
-                            if t < self.inner.speedtm {
-                                let wt = self.inner.speedtm - t;
-                                log::debug!(
-                                    "write1 limit({}b/500ms) down ({}) uses:{}ms, waits:{}ms",
-                                    lmv,
-                                    ln,
-                                    t.as_millis(),
-                                    wt.as_millis()
-                                );
-                                task::sleep(wt).await;
-                            }
-                        };
-                        ts = SystemTime::now();
-                        ln = 0;
-                    }
-                }
             } else if self.inner.endr2 {
                 break;
             } else {
-                task::sleep(Duration::from_millis(2)).await;
+                self.inner.notw.wait(NOTIFY_RECHECK).await;
             }
         }
         Ok(())
@@ -271,9 +247,14 @@ impl Proxyer {
                 return Err(ruisutil::ioerr("read size=0", None));
             }
             self.max_wait(2).await;
-            let mut lkv = self.inner.bufw.write().await;
-            lkv.pushs(Arc::new(buf), 0, n);
-            *count += n;
+            self.inner.traffic.down(n);
+            limiter::waits_down(&self.inner.down, &self.inner.cfg.budgets, n).await;
+            {
+                let mut lkv = self.inner.bufw.write().await;
+                lkv.pushs(Arc::new(buf), 0, n);
+                *count += n;
+            }
+            self.inner.notw.notify();
         }
         Ok(())
     }
@@ -281,29 +262,109 @@ impl Proxyer {
     pub async fn write2(&self, count: &mut usize) -> io::Result<()> {
         let ins = unsafe { self.inner.muts() };
         while !self.inner.ctx.done() {
-            {
-                let lkv = self.inner.buflcw.read().await;
-                if lkv.len() <= 0 {
-                    if self.inner.endr1 {
-                        break;
-                    }
-                    task::sleep(Duration::from_millis(2)).await;
-                    continue;
-                }
-            }
             let bts = {
                 let mut lkv = self.inner.buflcw.write().await;
                 lkv.pull()
             };
             if let Some(v) = bts {
-                ruisutil::tcp_write_async(&self.inner.ctx, &mut ins.connlc, &v).await?;
+                self.inner.roomlcw.notify();
+                ins.connlc.write_all(&self.inner.ctx, &v).await?;
                 *count += v.len();
             } else if self.inner.endr1 {
                 break;
             } else {
-                task::sleep(Duration::from_millis(2)).await;
+                self.inner.notlcw.wait(NOTIFY_RECHECK).await;
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use async_std::{
+        io::{ReadExt, WriteExt},
+        net::{TcpListener, TcpStream},
+        task,
+    };
+
+    use super::{Proxyer, ProxyerCfg};
+    use crate::engine::conn::ProxyConn;
+
+    // the user and system time of this process
+    fn cpu_time() -> Duration {
+        let mut ru: libc::rusage = unsafe { std::mem::zeroed() };
+        unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut ru) };
+        let tm = |v: libc::timeval| Duration::new(v.tv_sec as u64, v.tv_usec as u32 * 1000);
+        tm(ru.ru_utime) + tm(ru.ru_stime)
+    }
+    async fn pair(lsr: &TcpListener) -> (TcpStream, TcpStream) {
+        let conn = TcpStream::connect(lsr.local_addr().unwrap()).await.unwrap();
+        let (accs, _) = lsr.accept().await.unwrap();
+        (conn, accs)
+    }
+    // a proxyer between two loopback pairs,the outer ends are handed back
+    async fn proxy(lsr: &TcpListener, n: usize) -> (Proxyer, TcpStream, TcpStream) {
+        let (cli, conn) = pair(lsr).await;
+        let (connlc, tgt) = pair(lsr).await;
+        let cfg = ProxyerCfg {
+            ids: format!("bench{}", n),
+            limit: None,
+            stats: vec![],
+            budgets: vec![],
+        };
+        let ctx = ruisutil::Context::background(None);
+        let px = Proxyer::new(ctx, cfg, ProxyConn::Tcp(conn), ProxyConn::Tcp(connlc));
+        let c = px.clone();
+        task::spawn(c.start());
+        (px, cli, tgt)
+    }
+
+    /// idle cpu and round trip latency through proxyers,compare a build before and after with
+    /// cargo test --release bench_proxyer -- --ignored --nocapture
+    /// no numbers are kept here,they only mean something on the same machine and ruisutil checkout
+    #[test]
+    #[ignore]
+    fn bench_proxyer() {
+        task::block_on(async {
+            let lsr = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let mut idles = Vec::new();
+            for i in 0..500 {
+                idles.push(proxy(&lsr, i).await);
+            }
+            task::sleep(Duration::from_secs(1)).await;
+            let (cpus, tms) = (cpu_time(), Instant::now());
+            task::sleep(Duration::from_secs(5)).await;
+            let cpus = cpu_time() - cpus;
+            println!(
+                "idle {} proxyers: cpu {:?}/s",
+                idles.len(),
+                cpus.div_f64(tms.elapsed().as_secs_f64())
+            );
+
+            let (px, mut cli, mut tgt) = proxy(&lsr, idles.len()).await;
+            let (mut buf, mut lats) = ([0u8; 64], Vec::new());
+            for _ in 0..2000 {
+                let tms = Instant::now();
+                cli.write_all(&buf).await.unwrap();
+                tgt.read_exact(&mut buf).await.unwrap();
+                tgt.write_all(&buf).await.unwrap();
+                cli.read_exact(&mut buf).await.unwrap();
+                lats.push(tms.elapsed());
+            }
+            lats.sort();
+            println!(
+                "round trip: p50 {:?} p99 {:?} max {:?}",
+                lats[lats.len() / 2],
+                lats[lats.len() * 99 / 100],
+                lats[lats.len() - 1]
+            );
+            px.stop();
+            for (v, _, _) in idles.iter() {
+                v.stop();
+            }
+        });
+    }
+}
@@ -1,4 +1,10 @@
-use std::io;
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
 crate::cfg_unix! {
   use std::os::unix::io::{AsRawFd};
@@ -11,29 +17,122 @@ crate::cfg_windows! {
 }
 
 use async_std::{
-    net::{TcpListener, TcpStream},
+    channel::{self, Sender},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::Mutex,
     task,
 };
 use futures::StreamExt;
 use ruisutil::ArcMut;
 
-use crate::entity::node::ProxyGoto;
+use crate::{
+    entity::{
+        conf::{ProxyInfoConf, ProxyInfoGoto, ProxyInfoRoute},
+        node::{ProxyGoto, RuleRoute},
+        proxy::ProxyGotoHealth,
+        util::{ProxyLimit, TrafficInfo},
+    },
+    utils::{self, sign},
+};
 
 use super::{
+    balance::{ActiveGuard, Balancer},
+    conn::ProxyConn,
+    health::Health,
+    limiter::{Budget, ConnCaps},
+    nodes::ProxyOpts,
+    sniff,
+    stats::TrafficStat,
     proxyer::{Proxyer, ProxyerCfg},
+    udper::{self, UdpSession},
     NodeEngine, ProxyEngine,
 };
 
 pub struct RuleCfg {
     pub name: String,
+    pub protocol: String,
     pub bind_host: String,
     pub bind_port: i32,
     pub goto: Vec<ProxyGoto>,
+    pub visit_key: Option<String>,
+    pub mode: String,
+    pub routes: Vec<RuleRoute>,
+    pub balance: String,
+    pub health: bool,
+    // shared by all conns of the rule,as loaded:set_budget changes the rule's Budget only
+    pub budget: Option<ProxyLimit>,
+    // accept caps,0 is no cap
+    pub max_conns: u32,
+    pub max_conns_per_ip: u32,
+    pub conn_rate: u32,
+    // client cidrs,deny wins,as loaded:set_acl changes the rule's acl only
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+    // the conf file this rule is saved in
+    pub file: Option<String>,
+}
+impl RuleCfg {
+    /// back to the yml form of proxys_path
+    pub fn conv_conf(&self, stop: bool) -> ProxyInfoConf {
+        ProxyInfoConf {
+            name: Some(self.name.clone()),
+            stop: if stop { Some(true) } else { None },
+            protocol: Some(self.protocol.clone()),
+            bind: if self.bind_port == 0 {
+                String::new()
+            } else {
+                format!("{}:{}", self.bind_host.as_str(), self.bind_port)
+            },
+            visit_key: self.visit_key.clone(),
+            mode: Some(self.mode.clone()),
+            balance: Some(self.balance.clone()),
+            health_check: if self.health { Some(true) } else { None },
+            budget: self.budget.clone(),
+            max_conns: Some(self.max_conns).filter(|v| *v > 0),
+            max_conns_per_ip: Some(self.max_conns_per_ip).filter(|v| *v > 0),
+            conn_rate: Some(self.conn_rate).filter(|v| *v > 0),
+            allow: Some(self.allow.clone()).filter(|v| !v.is_empty()),
+            deny: Some(self.deny.clone()).filter(|v| !v.is_empty()),
+            proxys: convs_info_goto(&self.goto),
+            routes: if self.routes.is_empty() {
+                None
+            } else {
+                Some(
+                    self.routes
+                        .iter()
+                        .map(|v| ProxyInfoRoute {
+                            host: v.host.clone(),
+                            path: v.path.clone(),
+                            proxys: convs_info_goto(&v.goto),
+                        })
+                        .collect(),
+                )
+            },
+        }
+    }
+}
+fn convs_info_goto(gotos: &[ProxyGoto]) -> Vec<ProxyInfoGoto> {
+    gotos
+        .iter()
+        .map(|v| ProxyInfoGoto {
+            proxy: format!("{}:{}", v.proxy_host.as_str(), v.proxy_port),
+            localhost: v.localhost.clone(),
+            limit: v.limit.clone(),
+            weight: v.weight,
+            proxy_protocol: v.proxy_protocol.clone(),
+            compress: v.compress,
+            encrypt: v.encrypt,
+        })
+        .collect()
 }
 #[derive(Clone)]
 pub struct RuleProxy {
     inner: ArcMut<Inner>,
 }
+
+// the client and listener addrs of a conn
+type Origin = (Option<SocketAddr>, Option<SocketAddr>);
+
 struct Inner {
     ctx: ruisutil::Context,
     egn: ProxyEngine,
@@ -42,12 +141,34 @@ struct Inner {
     stat: i32,
     msgs: Option<String>,
     lsr: Option<TcpListener>,
+    udps: Mutex<HashMap<SocketAddr, Sender<Box<[u8]>>>>,
+    blc: Arc<Balancer>,
+    health: Health,
+    traffic: Arc<TrafficStat>,
+    budget: Arc<Budget>,
+    caps: Arc<ConnCaps>,
+    acl: std::sync::RwLock<RuleAcl>,
+}
+// the parsed acl with the lists it came from
+struct RuleAcl {
+    ips: utils::IpAcl,
+    allow: Vec<String>,
+    deny: Vec<String>,
 }
 
 impl RuleProxy {
     pub fn new(egn: ProxyEngine, node: NodeEngine, cfg: RuleCfg) -> Self {
         let ctx = ruisutil::Context::background(None);
         ctx.stop();
+        let blc = Arc::new(Balancer::new(cfg.balance.as_str()));
+        let budget = Budget::new(cfg.budget.clone());
+        let caps = ConnCaps::new(cfg.max_conns, cfg.max_conns_per_ip, cfg.conn_rate);
+        // checked by conv_rule,a bad list here lets all in
+        let acl = RuleAcl {
+            ips: utils::IpAcl::parse(&cfg.allow, &cfg.deny).unwrap_or_default(),
+            allow: cfg.allow.clone(),
+            deny: cfg.deny.clone(),
+        };
         Self {
             inner: ArcMut::new(Inner {
                 ctx: ctx,
@@ -57,6 +178,13 @@ impl RuleProxy {
                 stat: 0,
                 msgs: Some("wait start...".to_string()),
                 lsr: None,
+                udps: Mutex::new(HashMap::new()),
+                blc,
+                health: Health::new(),
+                traffic: TrafficStat::new(),
+                budget,
+                caps,
+                acl: std::sync::RwLock::new(acl),
             }),
         }
     }
@@ -85,6 +213,12 @@ impl RuleProxy {
                 ins.msgs = Some("bind is closed!".to_string());
             }
         });
+        if self.inner.cfg.health && self.inner.cfg.protocol == "tcp" {
+            let c = self.clone();
+            task::spawn(async move {
+                c.run_health().await;
+            });
+        }
         Ok(())
     }
     crate::cfg_unix! {
@@ -114,7 +248,22 @@ impl RuleProxy {
         }
     }
     pub async fn run(&self) -> io::Result<()> {
+        if self.inner.cfg.protocol == "udp" {
+            return self.run_udp().await;
+        }
         let ins = unsafe { self.inner.muts() };
+        if self.inner.cfg.bind_port == 0 {
+            ins.stat = 1;
+            let msgs = match &self.inner.cfg.visit_key {
+                Some(v) if !v.is_empty() => "visitor only",
+                _ => "visitor only,but no visit_key",
+            };
+            ins.msgs = Some(msgs.to_string());
+            while !self.inner.ctx.done() {
+                task::sleep(Duration::from_millis(500)).await;
+            }
+            return Ok(());
+        }
         let addr = format!("{}:{}", self.inner.cfg.bind_host, self.inner.cfg.bind_port);
         let lsr = TcpListener::bind(addr.as_str()).await?;
         ins.lsr = Some(lsr);
@@ -127,9 +276,28 @@ impl RuleProxy {
                     None => break,
                     Some(v) => match v {
                         Ok(conn) => {
+                            let ip = conn.peer_addr().ok().map(|v| v.ip());
+                            // a denied client takes no cap
+                            if !self.allowed(ip.as_ref()) {
+                                log::info!(
+                                    "rule {} deny conn from {}",
+                                    self.inner.cfg.name.as_str(),
+                                    ip.map(|v| v.to_string()).unwrap_or_default()
+                                );
+                                continue;
+                            }
+                            let guard = match ConnCaps::acquire(&self.inner.caps, ip) {
+                                Err(e) => {
+                                    log::debug!("{} refuse conn:{}", self.inner.cfg.name, e);
+                                    self.inner.traffic.reject();
+                                    continue;
+                                }
+                                Ok(v) => v,
+                            };
                             let c = self.clone();
                             task::spawn(async move {
                                 c.run_cli(conn).await;
+                                std::mem::drop(guard);
                             });
                         }
                         Err(e) => {
@@ -151,6 +319,7 @@ impl RuleProxy {
         Ok(())
     }
     async fn run_cli(&self, conn: TcpStream) {
+        let origin = (conn.peer_addr().ok(), conn.local_addr().ok());
         if let Ok(addr) = conn.peer_addr() {
             log::debug!(
                 "listen {}:{} incoming from:{}",
@@ -159,9 +328,137 @@ impl RuleProxy {
                 addr,
             );
         }
-        for v in &self.inner.cfg.goto {
-            match self.inner.node.wait_connlc(v).await {
-                Err(e) => log::error!("run_cli node.proxy err:{}", e),
+        if self.inner.cfg.mode == "http" {
+            self.run_cli_http(conn, origin).await;
+            return;
+        }
+        if self.inner.cfg.mode == "tls-sni" {
+            self.run_cli_sni(conn, origin).await;
+            return;
+        }
+        if let Some((v, connlc, actv)) = self.wait_gotos(&self.inner.cfg.goto, origin).await {
+            self.proxy(v, actv, ProxyConn::Tcp(conn), connlc, None).await;
+        }
+    }
+    /// actv keeps the goto counted as active until the conn ends
+    pub async fn proxy(
+        &self,
+        v: &ProxyGoto,
+        actv: ActiveGuard,
+        conn: ProxyConn,
+        connlc: ProxyConn,
+        firsts: Option<Box<[u8]>>,
+    ) {
+        let _actv = actv;
+        let opts = ProxyOpts {
+            rule: Some(self.inner.cfg.name.as_str()),
+            firsts,
+            stats: vec![self.inner.traffic.clone()],
+            budgets: vec![self.inner.budget.clone()],
+        };
+        self.inner.node.proxy(v, conn, connlc, opts).await;
+    }
+    // one routed request per conn,it is sent with Connection: close so the client
+    // comes back on a new conn for the next one and that is routed again.
+    // requests pipelined behind it are not sent on,the client retries them
+    async fn run_cli_http(&self, mut conn: TcpStream, origin: Origin) {
+        let firsts = match sniff::read_http_head(&mut conn).await {
+            Err(e) => {
+                log::debug!("run_cli_http read head err:{}", e);
+                return;
+            }
+            Ok(v) => v,
+        };
+        let (host, path) = match sniff::parse_http_head(&firsts) {
+            None => {
+                sniff::http_error(&mut conn, 400, "Bad Request").await;
+                return;
+            }
+            Some(v) => v,
+        };
+        let gotos = match self.route_goto(host.as_str(), path.as_str()) {
+            None => {
+                log::info!("http route not found:{}{}", host.as_str(), path.as_str());
+                self.inner.traffic.miss();
+                sniff::http_error(&mut conn, 404, "Not Found").await;
+                return;
+            }
+            Some(v) => v,
+        };
+        match self.wait_gotos(gotos, origin).await {
+            None => sniff::http_error(&mut conn, 502, "Bad Gateway").await,
+            Some((v, connlc, actv)) => {
+                let firsts = sniff::http_close(&firsts);
+                self.proxy(v, actv, ProxyConn::Tcp(conn), connlc, Some(firsts)).await
+            }
+        }
+    }
+    // tls is not terminated,the ClientHello is replayed to the backend as it is
+    async fn run_cli_sni(&self, mut conn: TcpStream, origin: Origin) {
+        let firsts = match sniff::read_tls_hello(&mut conn).await {
+            Err(e) => {
+                log::debug!("run_cli_sni read hello err:{}", e);
+                return;
+            }
+            Ok(v) => v,
+        };
+        let host = sniff::parse_sni(&firsts).unwrap_or_default();
+        let gotos = match self.route_goto(host.as_str(), "") {
+            None => {
+                log::info!("sni route not found:{}", host.as_str());
+                self.inner.traffic.miss();
+                return;
+            }
+            Some(v) => v,
+        };
+        if let Some((v, connlc, actv)) = self.wait_gotos(gotos, origin).await {
+            self.proxy(v, actv, ProxyConn::Tcp(conn), connlc, Some(firsts)).await;
+        }
+    }
+    /// routes matching the host,the longest path prefix wins;rule gotos are the default.
+    fn route_goto(&self, host: &str, path: &str) -> Option<&Vec<ProxyGoto>> {
+        let mut rts: Option<&RuleRoute> = None;
+        for v in &self.inner.cfg.routes {
+            if !utils::name_match(v.host.as_str(), host) {
+                continue;
+            }
+            let pths = v.path.as_deref().unwrap_or("");
+            if !sniff::path_prefix(pths, path) {
+                continue;
+            }
+            match rts {
+                Some(r) if r.path.as_deref().unwrap_or("").len() >= pths.len() => {}
+                _ => rts = Some(v),
+            }
+        }
+        match rts {
+            Some(v) => Some(&v.goto),
+            None if !self.inner.cfg.goto.is_empty() => Some(&self.inner.cfg.goto),
+            None => None,
+        }
+    }
+    /// the first goto whose node gives a conn,tried in the order of the balance
+    pub async fn wait_goto(&self) -> Option<(&ProxyGoto, ProxyConn, ActiveGuard)> {
+        self.wait_gotos(&self.inner.cfg.goto, (None, None)).await
+    }
+    // a goto counts as active from the dial on,so least-conn sees the conns still dialling
+    async fn wait_gotos<'a>(
+        &self,
+        gotos: &'a [ProxyGoto],
+        origin: Origin,
+    ) -> Option<(&'a ProxyGoto, ProxyConn, ActiveGuard)> {
+        let (src, dst) = (origin.0.map(|v| v.to_string()), origin.1.map(|v| v.to_string()));
+        for i in self.inner.blc.order(gotos, origin.0.map(|v| v.ip())) {
+            let v = &gotos[i];
+            if self.inner.health.is_down(v) {
+                continue;
+            }
+            let actv = self.inner.blc.active(v);
+            match self.inner.node.wait_connlc(v, None, src.clone(), dst.clone()).await {
+                Err(e) => {
+                    log::error!("run_cli node.proxy err:{}", e);
+                    self.inner.traffic.dial_fail();
+                }
                 Ok(connlc) => {
                     let locals = match &v.localhost {
                         None => "<nil>",
@@ -175,12 +472,205 @@ impl RuleProxy {
                         locals,
                         v.proxy_port
                     );
-                    self.inner.node.proxy(v, conn, connlc).await;
+                    return Some((v, connlc, actv));
+                }
+            }
+        }
+        None
+    }
+    // probe each goto when it is due,the node dials the target and reports back
+    async fn run_health(&self) {
+        // a restart replaces inner.ctx,this loop ends with the ctx it started with
+        let ctx = self.inner.ctx.clone();
+        while !ctx.done() {
+            for v in self.gotos() {
+                if ctx.done() {
                     break;
                 }
+                if !self.inner.health.due(v) {
+                    continue;
+                }
+                let rt = self.inner.node.probe(v).await;
+                if let Err(e) = &rt {
+                    log::debug!(
+                        "rule {} probe {}:{} err:{}",
+                        self.inner.cfg.name.as_str(),
+                        v.proxy_host.as_str(),
+                        v.proxy_port,
+                        e
+                    );
+                }
+                self.inner.health.report(v, rt);
+            }
+            task::sleep(Duration::from_secs(1)).await;
+        }
+    }
+    fn gotos(&self) -> Vec<&ProxyGoto> {
+        let mut rts: Vec<&ProxyGoto> = self.inner.cfg.goto.iter().collect();
+        for v in &self.inner.cfg.routes {
+            rts.extend(v.goto.iter());
+        }
+        rts
+    }
+    /// wait for the listener to be closed after stop,at most 5s
+    pub async fn wait_closed(&self) {
+        for _ in 0..50 {
+            if self.inner.stat != 1 {
+                return;
             }
+            task::sleep(Duration::from_millis(100)).await;
         }
     }
+    pub fn visit_check(&self, key: &Option<String>) -> io::Result<()> {
+        if self.stopd() {
+            return Err(ruisutil::ioerr("rule is stopped", None));
+        }
+        if self.inner.cfg.protocol != "tcp" {
+            return Err(ruisutil::ioerr("rule protocol not support visit", None));
+        }
+        // visiting is opt in,a rule without a visit_key is not reachable from nodes
+        let vs = match &self.inner.cfg.visit_key {
+            Some(v) if !v.is_empty() => v,
+            _ => return Err(ruisutil::ioerr("rule not open to visit", None)),
+        };
+        match key {
+            Some(v) if sign::same_secret(v, vs) => Ok(()),
+            _ => Err(ruisutil::ioerr("visit key err", None)),
+        }
+    }
+
+    async fn run_udp(&self) -> io::Result<()> {
+        let ins = unsafe { self.inner.muts() };
+        let addr = format!("{}:{}", self.inner.cfg.bind_host, self.inner.cfg.bind_port);
+        let lsr = Arc::new(UdpSocket::bind(addr.as_str()).await?);
+        ins.stat = 1;
+
+        let mut buf = vec![0u8; udper::UDP_PACKET_MAX].into_boxed_slice();
+        while !self.inner.ctx.done() {
+            let (n, addr) = match async_std::io::timeout(
+                Duration::from_secs(1),
+                lsr.recv_from(&mut buf),
+            )
+            .await
+            {
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => {
+                    // windows reports icmp port unreachable on the next recv
+                    log::debug!("udp recv err:{}", e);
+                    continue;
+                }
+                Ok(v) => v,
+            };
+            let bts: Box<[u8]> = buf[..n].into();
+            let mut lkv = self.inner.udps.lock().await;
+            if let Some(sdr) = lkv.get(&addr) {
+                match sdr.try_send(bts) {
+                    Ok(_) => continue,
+                    Err(channel::TrySendError::Full(_)) => {
+                        log::debug!("udp session {} queue full,drop packet", addr);
+                        continue;
+                    }
+                    Err(channel::TrySendError::Closed(v)) => {
+                        lkv.remove(&addr);
+                        self.new_udp_cli(&mut lkv, lsr.clone(), addr, v);
+                    }
+                }
+            } else {
+                self.new_udp_cli(&mut lkv, lsr.clone(), addr, bts);
+            }
+        }
+        log::debug!(
+            "{}:{} udp proxy stop!!",
+            self.inner.cfg.bind_host.as_str(),
+            self.inner.cfg.bind_port
+        );
+        let mut lkv = self.inner.udps.lock().await;
+        for (_, v) in lkv.iter() {
+            v.close();
+        }
+        lkv.clear();
+        Ok(())
+    }
+    fn new_udp_cli(
+        &self,
+        lkv: &mut HashMap<SocketAddr, Sender<Box<[u8]>>>,
+        lsr: Arc<UdpSocket>,
+        addr: SocketAddr,
+        bts: Box<[u8]>,
+    ) {
+        // each packet of a denied client gets here,so debug only
+        if !self.allowed(Some(&addr.ip())) {
+            log::debug!("rule {} deny udp from {}", self.inner.cfg.name.as_str(), addr);
+            return;
+        }
+        // a session takes a cap as a tcp conn does,a refused client is counted per packet
+        let guard = match ConnCaps::acquire(&self.inner.caps, Some(addr.ip())) {
+            Err(e) => {
+                log::debug!("{} refuse udp from {}:{}", self.inner.cfg.name, addr, e);
+                self.inner.traffic.reject();
+                return;
+            }
+            Ok(v) => v,
+        };
+        let (sdr, rcv) = channel::bounded(udper::UDP_QUEUE_MAX);
+        if let Err(e) = sdr.try_send(bts) {
+            log::debug!("udp session {} send first err:{}", addr, e);
+        }
+        lkv.insert(addr, sdr);
+        let c = self.clone();
+        task::spawn(async move {
+            c.run_udp_cli(lsr, addr, rcv).await;
+            std::mem::drop(guard);
+            let mut lkv = c.inner.udps.lock().await;
+            if let Some(v) = lkv.get(&addr) {
+                if v.is_closed() {
+                    lkv.remove(&addr);
+                }
+            }
+        });
+    }
+    async fn run_udp_cli(
+        &self,
+        lsr: Arc<UdpSocket>,
+        addr: SocketAddr,
+        rcv: channel::Receiver<Box<[u8]>>,
+    ) {
+        log::debug!(
+            "listen udp {}:{} incoming from:{}",
+            self.inner.cfg.bind_host.as_str(),
+            self.inner.cfg.bind_port,
+            addr,
+        );
+        let gotos = &self.inner.cfg.goto;
+        for i in self.inner.blc.order(gotos, Some(addr.ip())) {
+            let v = &gotos[i];
+            let _actv = self.inner.blc.active(v);
+            match self.inner.node.wait_connlc(v, Some("udp"), None, None).await {
+                Err(e) => {
+                    log::error!("run_udp_cli node.proxy err:{}", e);
+                    self.inner.traffic.dial_fail();
+                }
+                Ok(connlc) => {
+                    let mut stats = vec![self.inner.traffic.clone()];
+                    if let Some(v) = self.inner.node.traffic(&v.proxy_host).await {
+                        stats.push(v);
+                    }
+                    let sess = UdpSession::new(
+                        self.inner.ctx.clone(),
+                        format!("{}:{}", v.proxy_host.as_str(), v.proxy_port),
+                        connlc,
+                        lsr,
+                        addr,
+                        rcv,
+                        stats,
+                    );
+                    sess.start().await;
+                    return;
+                }
+            }
+        }
+        rcv.close();
+    }
 
     pub fn conf(&self) -> &RuleCfg {
         &self.inner.cfg
@@ -191,4 +681,50 @@ impl RuleProxy {
     pub fn msg(&self) -> Option<String> {
         self.inner.msgs.clone()
     }
+    pub fn traffic(&self) -> TrafficInfo {
+        self.inner.traffic.info()
+    }
+    fn allowed(&self, ip: Option<&IpAddr>) -> bool {
+        match self.inner.acl.read() {
+            Err(_) => false,
+            Ok(v) => v.ips.allowed(ip),
+        }
+    }
+    /// for new conns,the live ones are kept
+    pub fn set_acl(&self, allow: Vec<String>, deny: Vec<String>) -> io::Result<()> {
+        let ips = utils::IpAcl::parse(&allow, &deny)?;
+        if let Ok(mut v) = self.inner.acl.write() {
+            *v = RuleAcl { ips, allow, deny };
+        }
+        Ok(())
+    }
+    /// the allow and deny lists now
+    pub fn acl_lists(&self) -> (Vec<String>, Vec<String>) {
+        match self.inner.acl.read() {
+            Err(_) => (Vec::new(), Vec::new()),
+            Ok(v) => (v.allow.clone(), v.deny.clone()),
+        }
+    }
+    /// applied to the live conns too
+    pub fn set_budget(&self, limit: Option<ProxyLimit>) {
+        self.inner.budget.set(limit);
+    }
+    pub fn budget_limit(&self) -> Option<ProxyLimit> {
+        self.inner.budget.limit()
+    }
+    /// the yml form with what the api set since it was loaded
+    pub fn conv_conf(&self, stop: bool) -> ProxyInfoConf {
+        let mut rts = self.inner.cfg.conv_conf(stop);
+        rts.budget = self.budget_limit();
+        let (allow, deny) = self.acl_lists();
+        rts.allow = Some(allow).filter(|v| !v.is_empty());
+        rts.deny = Some(deny).filter(|v| !v.is_empty());
+        rts
+    }
+    pub fn health(&self) -> Option<Vec<ProxyGotoHealth>> {
+        if !self.inner.cfg.health {
+            return None;
+        }
+        Some(self.inner.health.list(&self.gotos()))
+    }
 }
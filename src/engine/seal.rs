@@ -0,0 +1,192 @@
+use std::{io, net::Shutdown};
+
+use chacha20poly1305::{aead::Aead, ChaCha20Poly1305, KeyInit, Nonce};
+
+use super::conn::ProxyConn;
+use crate::utils::sign;
+
+// plain bytes of a frame,a frame is u16 BE len + sealed bytes + 16 byte tag
+const SEAL_FRAME_MAX: usize = 16 * 1024;
+const SEAL_TAG_LEN: usize = 16;
+
+fn open_err() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "seal open err")
+}
+
+/// the keys of a tunnel,one each way,bound to the tunnel xids
+fn ciphers(secret: &str, xids: &str, server: bool) -> (ChaCha20Poly1305, ChaCha20Poly1305) {
+    let up = sign::derive_key(secret, format!("hbproxy-seal\n{}\nserver", xids).as_str());
+    let down = sign::derive_key(secret, format!("hbproxy-seal\n{}\nnode", xids).as_str());
+    let (up, down) = (ChaCha20Poly1305::new(&up.into()), ChaCha20Poly1305::new(&down.into()));
+    if server {
+        (up, down)
+    } else {
+        (down, up)
+    }
+}
+// frames are counted from 0,a dropped,replayed or reordered frame fails to open
+fn nonce(n: u64) -> Nonce {
+    let mut bts = [0u8; 12];
+    bts[4..].copy_from_slice(&n.to_be_bytes());
+    bts.into()
+}
+
+struct Sealer {
+    aead: ChaCha20Poly1305,
+    n: u64,
+}
+impl Sealer {
+    fn seal(&mut self, bts: &[u8]) -> io::Result<Vec<u8>> {
+        let mut rts = Vec::with_capacity(bts.len() + 32);
+        for v in bts.chunks(SEAL_FRAME_MAX) {
+            let cts = match self.aead.encrypt(&nonce(self.n), v) {
+                Err(_) => return Err(ruisutil::ioerr("seal err", None)),
+                Ok(v) => v,
+            };
+            self.n += 1;
+            rts.extend_from_slice(&(cts.len() as u16).to_be_bytes());
+            rts.extend_from_slice(&cts);
+        }
+        Ok(rts)
+    }
+}
+
+struct Opener {
+    aead: ChaCha20Poly1305,
+    n: u64,
+    ins: Vec<u8>,
+}
+impl Opener {
+    fn feed(&mut self, bts: &[u8]) {
+        self.ins.extend_from_slice(bts);
+    }
+    /// the plain bytes of the next whole frame
+    fn open(&mut self) -> io::Result<Option<Vec<u8>>> {
+        if self.ins.len() < 2 {
+            return Ok(None);
+        }
+        let ln = u16::from_be_bytes([self.ins[0], self.ins[1]]) as usize;
+        if !(SEAL_TAG_LEN..=SEAL_FRAME_MAX + SEAL_TAG_LEN).contains(&ln) {
+            return Err(open_err());
+        }
+        if self.ins.len() < 2 + ln {
+            return Ok(None);
+        }
+        let rts = self.aead.decrypt(&nonce(self.n), &self.ins[2..2 + ln]);
+        let rts = rts.map_err(|_| open_err())?;
+        self.n += 1;
+        self.ins.drain(..2 + ln);
+        Ok(Some(rts))
+    }
+}
+
+/// a node tunnel sealed with chacha20poly1305,keyed by the node credential
+pub struct SealConn {
+    conn: ProxyConn,
+    sealer: Sealer,
+    opener: Opener,
+    outs: Vec<u8>,
+    pos: usize,
+}
+impl SealConn {
+    /// server: the server end of the tunnel
+    pub fn new(conn: ProxyConn, secret: &str, xids: &str, server: bool) -> Self {
+        let (seals, opens) = ciphers(secret, xids, server);
+        Self {
+            conn,
+            sealer: Sealer { aead: seals, n: 0 },
+            opener: Opener {
+                aead: opens,
+                n: 0,
+                ins: Vec::new(),
+            },
+            outs: Vec::new(),
+            pos: 0,
+        }
+    }
+    pub async fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos >= self.outs.len() {
+            if let Some(v) = self.opener.open()? {
+                self.outs = v;
+                self.pos = 0;
+                continue;
+            }
+            let mut bts = vec![0u8; 10240];
+            let n = self.conn.read(&mut bts).await?;
+            if n == 0 {
+                return Ok(0);
+            }
+            self.opener.feed(&bts[..n]);
+        }
+        let n = buf.len().min(self.outs.len() - self.pos);
+        buf[..n].copy_from_slice(&self.outs[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+    pub async fn write_all(&mut self, ctx: &ruisutil::Context, bts: &[u8]) -> io::Result<()> {
+        let bts = self.sealer.seal(bts)?;
+        self.conn.write_all(ctx, &bts).await
+    }
+    pub fn peer_addr(&self) -> Option<String> {
+        self.conn.peer_addr()
+    }
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        self.conn.shutdown(how)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ciphers, Opener, Sealer, SEAL_FRAME_MAX};
+
+    fn pair(secret: &str, xids: &str, opens: &str) -> (Sealer, Opener) {
+        let (aead, _) = ciphers(secret, xids, true);
+        let (_, open) = ciphers(opens, xids, false);
+        (Sealer { aead, n: 0 }, Opener { aead: open, n: 0, ins: Vec::new() })
+    }
+
+    #[test]
+    fn seals() {
+        let (mut sl, mut op) = pair("key", "x1", "key");
+        let msg = vec![7u8; SEAL_FRAME_MAX + 100];
+        let bts = sl.seal(&msg).unwrap();
+        assert_eq!(bts.len(), msg.len() + 2 * (2 + 16));
+        // frames come out whole,however the bytes are split
+        let mut rts = Vec::new();
+        for v in bts.chunks(1000) {
+            op.feed(v);
+            while let Some(v) = op.open().unwrap() {
+                rts.extend_from_slice(&v);
+            }
+        }
+        assert_eq!(rts, msg);
+
+        // a changed byte,a replayed frame
+        let bts = sl.seal(b"hello").unwrap();
+        let mut bad = bts.clone();
+        bad[5] ^= 1;
+        op.feed(&bad);
+        assert!(op.open().is_err());
+        let (mut sl, mut op) = pair("key", "x1", "key");
+        let bts = sl.seal(b"hello").unwrap();
+        op.feed(&bts);
+        op.feed(&bts);
+        assert_eq!(op.open().unwrap().unwrap(), b"hello");
+        assert!(op.open().is_err());
+
+        // another credential,another tunnel
+        let (mut sl, mut op) = pair("key", "x1", "key2");
+        op.feed(&sl.seal(b"hello").unwrap());
+        assert!(op.open().is_err());
+        let (mut sl, _) = pair("key", "x1", "key");
+        let (_, mut op) = pair("key", "x2", "key");
+        op.feed(&sl.seal(b"hello").unwrap());
+        assert!(op.open().is_err());
+        // a frame sent back to the end that sealed it
+        let (up, down) = ciphers("key", "x1", true);
+        let mut sl = Sealer { aead: up, n: 0 };
+        let mut op = Opener { aead: down, n: 0, ins: Vec::new() };
+        op.feed(&sl.seal(b"hello").unwrap());
+        assert!(op.open().is_err());
+    }
+}
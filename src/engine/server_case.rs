@@ -1,20 +1,42 @@
 use std::{
+    collections::HashMap,
     io,
-    time::{Duration, SystemTime},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
 };
 
 use async_std::task;
 
 use crate::{
     app::Application,
-    engine::{NodeEngine, NodeServerCfg, ProxyEngine, RuleCfg},
+    engine::{
+        conn::ProxyConn,
+        metrics,
+        nodes::ProxyOpts,
+        proxy::{conv_balance, conv_mode},
+        rule::RuleProxy,
+        tls, NodeEngine, NodeServerCfg, ProxyEngine, RuleCfg,
+    },
     entity::{
-        node::{NodeConnMsg, ProxyGoto, RegNodeRep, RegNodeReq, ProxyGotoReq},
-        proxy::RuleConfReq,
+        conf::NodeAuthConf,
+        node::{NodeConnMsg, NodeVisitMsg, ProxyGoto, ProxyGotoReq, RegNodeRep, RegNodeReq},
+        proxy::{BudgetListIt, BudgetListRep, ConnListRep, ProxyAclReq, RuleConfReq},
+        util::ProxyLimit,
     },
-    utils,
+    utils::{self, sign},
 };
 
+// the args handlers read,a hmac signed request must cover each one it carries
+const SIGNED_ARGS: &[&str] = &[
+    "node", "name", "xid", "err", "compress", "encrypt", "rule", "visit_key", "id", "up", "down",
+    "burst", "scope", "token",
+];
+// randoms kept at most,a full cache turns new requests away rather than forget a live one
+const NONCE_MAX: usize = 1 << 18;
+
 #[derive(Clone)]
 pub struct ServerCase {
     inner: ruisutil::ArcMut<Inner>,
@@ -24,13 +46,23 @@ struct Inner {
     node: NodeEngine,
     // nodes: RwLock<HashMap<String, NodeServer>>,
     time_check: bool,
+    sign_legacy: bool,
+    nonces: Mutex<HashMap<String, SystemTime>>,
+    // empty means any node name may join with server.key
+    nodes: Vec<NodeAuthConf>,
+    node_auth_fails: AtomicU64,
+    api_auth_fails: AtomicU64,
 }
 
 impl ServerCase {
-    pub fn new(ctx: ruisutil::Context) -> Self {
+    pub fn new(ctx: ruisutil::Context) -> io::Result<Self> {
+        let nodes = Self::load_nodes()?;
         let nd = NodeEngine::new(ctx.clone());
         let pxy = ProxyEngine::new(ctx.clone(), nd.clone());
-        Self {
+        if let Some(v) = &Application::get().conf {
+            nd.budget().set(v.server.budget.clone());
+        }
+        Ok(Self {
             inner: ruisutil::ArcMut::new(Inner {
                 proxy: pxy,
                 node: nd,
@@ -42,7 +74,73 @@ impl ServerCase {
                         Some(v) => *v,
                     },
                 },
+                sign_legacy: match &Application::get().conf {
+                    None => false,
+                    Some(v) => v.server.sign_legacy.unwrap_or(false),
+                },
+                nonces: Mutex::new(HashMap::new()),
+                nodes,
+                node_auth_fails: AtomicU64::new(0),
+                api_auth_fails: AtomicU64::new(0),
             }),
+        })
+    }
+    fn load_nodes() -> io::Result<Vec<NodeAuthConf>> {
+        let mut ls = Vec::new();
+        if let Some(conf) = &Application::get().conf {
+            if let Some(vs) = &conf.server.nodes {
+                ls.extend(vs.iter().cloned());
+            }
+            if let Some(pth) = &conf.server.nodes_path {
+                let vs: Vec<NodeAuthConf> = utils::ymlfile(pth)?;
+                ls.extend(vs);
+            }
+        }
+        for v in &ls {
+            if v.name.is_empty() || v.key.is_empty() {
+                return Err(ruisutil::ioerr("node name or key is empty", None));
+            }
+        }
+        Ok(ls)
+    }
+    fn node_conf(&self, name: &str) -> Option<&NodeAuthConf> {
+        if let Some(v) = self.inner.nodes.iter().find(|v| v.name == name) {
+            return Some(v);
+        }
+        self.inner
+            .nodes
+            .iter()
+            .find(|v| utils::name_match(v.name.as_str(), name))
+    }
+    fn node_key(&self, name: &str) -> Option<String> {
+        self.node_conf(name).map(|v| v.key.clone())
+    }
+    fn node_max_tunnels(&self, name: &str) -> u32 {
+        if let Some(v) = self.node_conf(name).and_then(|v| v.max_tunnels) {
+            return v;
+        }
+        match &Application::get().conf {
+            None => 0,
+            Some(v) => v.server.node_max_tunnels.unwrap_or(0),
+        }
+    }
+    /// the key a node signs with,None when nodes are not signed
+    fn node_secret(&self, name: &str) -> Option<String> {
+        let key = if self.inner.nodes.is_empty() {
+            Application::get().keys.clone()
+        } else {
+            self.node_key(name)
+        };
+        key.filter(|v| !v.is_empty())
+    }
+    /// with a node registry,the signed `node` arg must be the node the request acts for.
+    fn node_named(&self, c: &hbtp::Context, name: &str) -> bool {
+        if self.inner.nodes.is_empty() {
+            return true;
+        }
+        match c.get_arg("node") {
+            None => false,
+            Some(v) => v.as_str() == name,
         }
     }
 
@@ -51,16 +149,64 @@ impl ServerCase {
         task::spawn(async move {
             match c.inner.proxy.reload().await {
                 Err(e) => log::error!("proxy init reload err:{}", e),
-                Ok(_) => log::info!("proxy init reload ok"),
+                Ok(v) => log::info!(
+                    "proxy init reload ok:{} rules,{} errs",
+                    v.added.len(),
+                    v.errs.len()
+                ),
+            }
+            let watch = match &Application::get().conf {
+                None => false,
+                Some(v) => v.server.proxys_watch.unwrap_or(false),
+            };
+            if watch {
+                if let Err(e) = c.inner.proxy.watch() {
+                    log::error!("proxys watch err:{}", e);
+                }
             }
         });
     }
 
     pub fn authed_server(&self, c: &hbtp::Context) -> Option<&str> {
-        self.autheds(c, &Application::get().keys)
+        let rt = self.authed_node(c);
+        if rt.is_some() {
+            self.inner.node_auth_fails.fetch_add(1, Ordering::Relaxed);
+        }
+        rt
+    }
+    fn authed_node(&self, c: &hbtp::Context) -> Option<&str> {
+        if self.inner.nodes.is_empty() {
+            return self.autheds(c, &Application::get().keys);
+        }
+        let name = match c.get_arg("node") {
+            None => return Some("param node is nil"),
+            Some(v) => v,
+        };
+        match self.node_key(name.as_str()) {
+            None => {
+                log::warn!("node {} is not in the registry", name.as_str());
+                Some("node is not allowed")
+            }
+            Some(key) => self.autheds(c, &Some(key)),
+        }
     }
     pub fn authed_api(&self, c: &hbtp::Context) -> Option<&str> {
-        self.autheds(c, &Application::get().apikeys)
+        let rt = self.autheds(c, &Application::get().apikeys);
+        if rt.is_some() {
+            self.inner.api_auth_fails.fetch_add(1, Ordering::Relaxed);
+        }
+        rt
+    }
+
+    /// the prometheus text of nodes,rules and auth fails
+    pub async fn metrics(&self) -> String {
+        let nodes = self.inner.node.nodes().await;
+        let rules = self.inner.proxy.rules().await;
+        let fails = [
+            ("node", self.inner.node_auth_fails.load(Ordering::Relaxed)),
+            ("api", self.inner.api_auth_fails.load(Ordering::Relaxed)),
+        ];
+        metrics::render(&nodes, &rules, &fails)
     }
     fn autheds(&self, c: &hbtp::Context, key: &Option<String>) -> Option<&str> {
         match key {
@@ -84,63 +230,104 @@ impl ServerCase {
                 if tms.is_empty() || rands.len() < 20 || signs.is_empty() {
                     return Some("params has empty");
                 }
-                match ruisutil::strptime(tms.as_str(), "%+") {
+                let tm = match ruisutil::strptime(tms.as_str(), "%+") {
                     Err(_) => return Some("parse times err"),
-                    Ok(v) => match SystemTime::now().duration_since(v-Duration::from_secs(60*2)) {
-                        Err(e) => {
-                            if self.inner.time_check {
-                                return Some("duration times err");
-                            } else {
-                                let addrs = match c.peer_addr() {
-                                    Err(_) => "<nil>".to_string(),
-                                    Ok(vs) => vs,
-                                };
-
-                                log::warn!(
-                                    "client {} time since err but not check:{},err:{}",
-                                    addrs.as_str(),
-                                    tms.as_str(),
-                                    e
-                                );
-                            }
-                        }
-                        Ok(tm) => {
-                            // println!("time since:{}", tm.as_secs_f32());
-                            if tm > Duration::from_secs(120) {
-                                if self.inner.time_check {
-                                    return Some("time check err: since>120s");
-                                } else {
-                                    let addrs = match c.peer_addr() {
-                                        Err(_) => "<nil>".to_string(),
-                                        Ok(vs) => vs,
-                                    };
-                                    log::warn!(
-                                        "client {} time err but not check:{}",
-                                        addrs.as_str(),
-                                        tms.as_str()
-                                    );
-                                }
-                            }
-                        }
-                    },
+                    Ok(v) => v,
+                };
+                // a hmac sign is kept in the nonce cache till its times leave the window,
+                // so the window always holds for it
+                let hmacs = c.get_arg("sign_ver").as_deref() == Some(sign::SIGN_VER);
+                let checks = hmacs || self.inner.time_check;
+                if !sign::in_window(SystemTime::now(), tm) {
+                    if checks {
+                        return Some("time check err: times out of window");
+                    }
+                    let addrs = match tls::peer_addr(c) {
+                        Err(_) => "<nil>".to_string(),
+                        Ok(vs) => vs,
+                    };
+                    log::warn!(
+                        "client {} time err but not check:{}",
+                        addrs.as_str(),
+                        tms.as_str()
+                    );
                 }
                 // println!("tms:{},rands:{},signs:{}",tms,rands,signs);
-                let sign = ruisutil::md5str(format!(
-                    "{}{}{}{}",
-                    c.command(),
-                    tms.as_str(),
-                    rands.as_str(),
-                    vs.as_str()
-                ));
-                if sign.eq(&signs) {
-                    return None;
+                if let Some(errs) = self.check_sign(c, vs, &tms, &rands, &signs) {
+                    return Some(errs);
+                }
+                // an unchecked legacy times says nothing,keep its random a window from now
+                let expiry = if checks { tm } else { SystemTime::now() } + sign::SIGN_WINDOW;
+                self.nonce_check(rands.as_str(), expiry)
+            }
+        }
+    }
+
+    fn check_sign(
+        &self,
+        c: &hbtp::Context,
+        key: &str,
+        tms: &str,
+        rands: &str,
+        signs: &str,
+    ) -> Option<&str> {
+        match c.get_arg("sign_ver") {
+            Some(v) if v.as_str() == sign::SIGN_VER => {
+                let mut args = Vec::new();
+                let keys = c.get_arg("sign_args").unwrap_or_default();
+                for k in keys.split(',').filter(|k| !k.is_empty()) {
+                    match c.get_arg(k) {
+                        None => return Some("param sign_args not found"),
+                        Some(v) => args.push((k.to_string(), v)),
+                    }
+                }
+                let args: Vec<(&str, &str)> =
+                    args.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+                for k in SIGNED_ARGS {
+                    if c.get_arg(k).is_some() && !args.iter().any(|(ak, _)| ak == k) {
+                        log::debug!("param {} is not signed", k);
+                        return Some("param not signed");
+                    }
+                }
+                let bodys = c.get_bodys().map(|v| &v[..]);
+                let texts = sign::sign_texts(c.command(), tms, rands, &keys, &args[..], bodys);
+                if sign::hmac_check(key, texts.as_str(), signs) {
+                    None
                 } else {
-                    log::debug!("check sign err:{}!={}", sign.as_str(), signs.as_str());
-                    return Some("check sign err");
+                    log::debug!("check hmac sign err:{}", signs);
+                    Some("check sign err")
                 }
             }
-        };
-        // Some("auths not match end!!")
+            _ => {
+                if !self.inner.sign_legacy {
+                    return Some("legacy sign is disabled");
+                }
+                let sign = ruisutil::md5str(format!("{}{}{}{}", c.command(), tms, rands, key));
+                if sign.eq(signs) {
+                    None
+                } else {
+                    log::debug!("check sign err:{}!={}", sign.as_str(), signs);
+                    Some("check sign err")
+                }
+            }
+        }
+    }
+    /// expiry: when the request would no longer pass the times check
+    fn nonce_check(&self, rands: &str, expiry: SystemTime) -> Option<&'static str> {
+        let mut lkv = self.inner.nonces.lock().unwrap_or_else(|e| e.into_inner());
+        if lkv.contains_key(rands) {
+            return Some("param random is reused");
+        }
+        if lkv.len() >= NONCE_MAX {
+            let now = SystemTime::now();
+            lkv.retain(|_, v| *v > now);
+            if lkv.len() >= NONCE_MAX {
+                log::warn!("nonce cache is full,request refused");
+                return Some("too many requests");
+            }
+        }
+        lkv.insert(rands.to_string(), expiry);
+        None
     }
 
     pub async fn node_reg(&self, c: hbtp::Context) -> io::Result<()> {
@@ -148,6 +335,9 @@ impl ServerCase {
         if data.name.is_empty() {
             return c.res_string(hbtp::ResCodeErr, "name err").await;
         }
+        if !self.node_named(&c, data.name.as_str()) {
+            return c.res_string(hbtp::ResCodeAuth, "node name not match").await;
+        }
         match self.inner.node.reg_check(&data).await {
             0 => {}
             1 => log::debug!("replace node:{}", data.name.as_str()),
@@ -160,15 +350,25 @@ impl ServerCase {
             name: data.name.clone(),
             version: data.version.clone(),
             token: ruisutil::random(32),
+            mux: data.mux.unwrap_or(false),
+            probe: data.probe.unwrap_or(false),
+            max_tunnels: self.node_max_tunnels(data.name.as_str()),
+            key: self.node_secret(data.name.as_str()),
         };
 
         c.res_json(
             hbtp::ResCodeOk,
             &RegNodeRep {
                 token: cfg.token.clone(),
+                mux: Some(cfg.mux),
             },
         )
         .await?;
+        if let Some(v) = self.node_conf(data.name.as_str()) {
+            if v.budget.is_some() {
+                self.inner.node.node_budget_init(&data.name, v.budget.clone());
+            }
+        }
         self.inner.node.register(cfg, c.own_conn()).await?;
         Ok(())
     }
@@ -178,11 +378,15 @@ impl ServerCase {
         if data.name.is_empty() || data.xids.is_empty() {
             return Err(ruisutil::ioerr("param errs", None));
         }
+        if !self.node_named(&c, data.name.as_str()) {
+            return c.res_string(hbtp::ResCodeAuth, "node name not match").await;
+        }
+        let compress = c.get_arg("compress").as_deref() == Some("1");
+        let encrypt = c.get_arg("encrypt").as_deref() == Some("1");
         c.res_string(hbtp::ResCodeOk, "ok").await?;
-        self.inner
-            .node
-            .put_conn(&data.name, &data.xids, Some(c.own_conn()))
-            .await
+        let conn = Some(ProxyConn::Tcp(c.own_conn()));
+        let node = &self.inner.node;
+        node.put_conn(&data.name, &data.xids, conn, compress, encrypt).await
     }
 
     pub async fn node_conns(&self, c: hbtp::Context) -> io::Result<()> {
@@ -198,17 +402,105 @@ impl ServerCase {
             None => false,
             Some(v) => v == "1",
         };
+        let compress = c.get_arg("compress").as_deref() == Some("1");
+        let encrypt = c.get_arg("encrypt").as_deref() == Some("1");
         if name.is_empty() || xids.is_empty() {
             return Err(ruisutil::ioerr("param errs", None));
         }
+        if !self.node_named(&c, name.as_str()) {
+            return c.res_string(hbtp::ResCodeAuth, "node name not match").await;
+        }
         c.res_string(hbtp::ResCodeOk, "ok").await?;
 
         self.inner
             .node
-            .put_conn(&name, &xids, if iserr { None } else { Some(c.own_conn()) })
+            .put_conn(
+                &name,
+                &xids,
+                if iserr {
+                    None
+                } else {
+                    Some(ProxyConn::Tcp(c.own_conn()))
+                },
+                compress,
+                encrypt,
+            )
             .await
     }
 
+    /// an idle data conn a node parks for the next tunnel
+    pub async fn node_park(&self, c: hbtp::Context) -> io::Result<()> {
+        let name = match c.get_arg("name") {
+            None => return Err(ruisutil::ioerr("param err:name", None)),
+            Some(v) => v,
+        };
+        if !self.node_named(&c, name.as_str()) {
+            return c.res_string(hbtp::ResCodeAuth, "node name not match").await;
+        }
+        let node = match self.inner.node.find_node(&name).await {
+            Err(_) => return c.res_string(hbtp::ResCodeNotFound, "node not found").await,
+            Ok(v) => v,
+        };
+        // only the session that joined parks,the name alone is not enough
+        let token = c.get_arg("token").unwrap_or_default();
+        if !sign::same_secret(token.as_str(), node.conf().token.as_str()) {
+            return c.res_string(utils::HBTP_TOKEN_ERR, "token err").await;
+        }
+        if !node.parkable() {
+            return c.res_string(hbtp::ResCodeErr, "node pool is full").await;
+        }
+        c.res_string(hbtp::ResCodeOk, "ok").await?;
+        node.park(c.own_conn());
+        Ok(())
+    }
+
+    pub async fn node_visit(&self, c: hbtp::Context) -> io::Result<()> {
+        let data = NodeVisitMsg {
+            rule: match c.get_arg("rule") {
+                None => return c.res_string(hbtp::ResCodeErr, "param err:rule").await,
+                Some(v) => v,
+            },
+            key: c.get_arg("visit_key"),
+        };
+        let rule = match self.visit_rule(&data).await {
+            Err(e) => return c.res_string(hbtp::ResCodeErr, format!("{}", e).as_str()).await,
+            Ok(v) => v,
+        };
+        match rule.wait_goto().await {
+            None => c.res_string(hbtp::ResCodeErr, "all goto is err").await,
+            Some((v, connlc, actv)) => {
+                c.res_string(hbtp::ResCodeOk, "ok").await?;
+                rule.proxy(v, actv, ProxyConn::Tcp(c.own_conn()), connlc, None).await;
+                Ok(())
+            }
+        }
+    }
+    /// a visitor stream opened by a mux node
+    pub async fn visit(&self, data: NodeVisitMsg, conn: ProxyConn) {
+        let rule = match self.visit_rule(&data).await {
+            Err(e) => {
+                log::debug!("visit {} err:{}", data.rule.as_str(), e);
+                let _ = conn.shutdown(std::net::Shutdown::Both);
+                return;
+            }
+            Ok(v) => v,
+        };
+        match rule.wait_goto().await {
+            None => {
+                let _ = conn.shutdown(std::net::Shutdown::Both);
+            }
+            Some((v, connlc, actv)) => rule.proxy(v, actv, conn, connlc, None).await,
+        }
+    }
+    async fn visit_rule(&self, data: &NodeVisitMsg) -> io::Result<RuleProxy> {
+        let rule = match self.inner.proxy.get_rule(&data.rule).await {
+            None => return Err(ruisutil::ioerr("rule not found", None)),
+            Some(v) => v,
+        };
+        rule.visit_check(&data.key)?;
+        Ok(rule)
+    }
+
     pub async fn node_list(&self, c: hbtp::Context) -> io::Result<()> {
         let rts = self.inner.node.show_list().await?;
         c.res_json(hbtp::ResCodeOk, &rts).await
@@ -233,12 +525,15 @@ impl ServerCase {
 
     pub async fn node_proxy(&self, c: hbtp::Context) -> io::Result<()> {
         let data: ProxyGotoReq = c.body_json()?;
+        // the conn's listener addr is not known here,a proxy_protocol goto gets UNKNOWN/LOCAL
         for v in &data.proxys {
-            match self.inner.node.wait_connlc(&v).await {
+            match self.inner.node.wait_connlc(v, None, None, None).await {
                 Err(e) => log::error!("run_cli node.proxy err:{}", e),
                 Ok(connlc) => {
                     c.res_string(hbtp::ResCodeOk, "ok").await?;
-                    self.inner.node.proxy(&v, c.own_conn(), connlc).await;
+                    let conn = ProxyConn::Tcp(c.own_conn());
+                    let node = &self.inner.node;
+                    node.proxy(v, conn, connlc, ProxyOpts::default()).await;
                     return Ok(());
                 }
             }
@@ -247,12 +542,13 @@ impl ServerCase {
     }
 
     pub async fn proxy_reload(&self, c: hbtp::Context) -> io::Result<()> {
-        if let Err(e) = self.inner.proxy.reload().await {
-            log::debug!("proxy reload err:{}", e);
-            c.res_string(hbtp::ResCodeErr, format!("reload failed:{}", e).as_str())
-                .await
-        } else {
-            c.res_string(hbtp::ResCodeOk, "ok").await
+        match self.inner.proxy.reload().await {
+            Err(e) => {
+                log::debug!("proxy reload err:{}", e);
+                c.res_string(hbtp::ResCodeErr, format!("reload failed:{}", e).as_str())
+                    .await
+            }
+            Ok(v) => c.res_json(hbtp::ResCodeOk, &v).await,
         }
     }
 
@@ -289,22 +585,68 @@ impl ServerCase {
             if gov.proxy_port <= 0 {
                 return c.res_string(hbtp::ResCodeErr, "proxy port err").await;
             }
+            match gov.proxy_protocol.as_deref() {
+                None | Some("v1") | Some("v2") => {}
+                Some(_) => return c.res_string(hbtp::ResCodeErr, "proxy_protocol err").await,
+            }
             gotols.push(ProxyGoto {
                 proxy_host: gov.proxy_host.clone(),
                 proxy_port: gov.proxy_port,
                 localhost: None,
                 limit: gov.limit.clone(),
+                weight: gov.weight,
+                proxy_protocol: gov.proxy_protocol.clone(),
+                compress: gov.compress,
+                encrypt: gov.encrypt,
             })
         }
+        let protocol = match &data.protocol {
+            None => "tcp".to_string(),
+            Some(vs) => match vs.as_str() {
+                "tcp" | "udp" => vs.clone(),
+                _ => return c.res_string(hbtp::ResCodeErr, "protocol err").await,
+            },
+        };
+        let mode = match conv_mode(data.mode.as_deref(), protocol.as_str()) {
+            Err(e) => return c.res_string(hbtp::ResCodeErr, format!("{}", e).as_str()).await,
+            Ok(v) => v,
+        };
+        let balance = match conv_balance(data.balance.as_deref()) {
+            Err(e) => return c.res_string(hbtp::ResCodeErr, format!("{}", e).as_str()).await,
+            Ok(v) => v,
+        };
+        let mut routes = Vec::new();
+        for v in data.routes.iter().flatten() {
+            match v.conv_route() {
+                Err(e) => return c.res_string(hbtp::ResCodeErr, format!("{}", e).as_str()).await,
+                Ok(v) => routes.push(v),
+            }
+        }
         let cfg = RuleCfg {
             name: match &data.name {
                 None => format!("b{}{}", data.bind_port, ruisutil::random(5).as_str()),
                 Some(vs) => vs.clone(),
             },
+            protocol,
             bind_host: data.bind_host.clone(),
             bind_port: data.bind_port,
             goto: gotols,
+            visit_key: data.visit_key.clone(),
+            mode,
+            routes,
+            balance,
+            health: data.health_check.unwrap_or(false),
+            budget: data.budget.clone().filter(|v| v.up > 0 || v.down > 0),
+            max_conns: data.max_conns.unwrap_or(0),
+            max_conns_per_ip: data.max_conns_per_ip.unwrap_or(0),
+            conn_rate: data.conn_rate.unwrap_or(0),
+            allow: data.allow.clone().unwrap_or_default(),
+            deny: data.deny.clone().unwrap_or_default(),
+            file: None,
         };
+        if let Err(e) = utils::IpAcl::parse(&cfg.allow, &cfg.deny) {
+            return c.res_string(hbtp::ResCodeErr, format!("{}", e).as_str()).await;
+        }
         match self.inner.proxy.add_check(&cfg).await {
             0 => {}
             1 => return c.res_string(hbtp::ResCodeErr, "proxy name is exsit").await,
@@ -312,8 +654,10 @@ impl ServerCase {
             _ => return c.res_string(hbtp::ResCodeErr, "add check err").await,
         }
         let nms = cfg.name.clone();
-        self.inner.proxy.add_proxy(cfg, false).await?;
-        c.res_string(hbtp::ResCodeOk, nms.as_str()).await
+        if let Err(e) = self.inner.proxy.add_proxy(cfg, false).await {
+            return c.res_string(hbtp::ResCodeErr, format!("{}", e).as_str()).await;
+        }
+        self.proxy_persist(c, &nms, nms.as_str()).await
     }
     pub async fn proxy_list(&self, c: hbtp::Context) -> io::Result<()> {
         let rts = self.inner.proxy.show_list().await?;
@@ -326,7 +670,7 @@ impl ServerCase {
             return c.res_string(hbtp::ResCodeOk, "param name err").await;
         };
         self.inner.proxy.start(&nms).await?;
-        c.res_string(hbtp::ResCodeOk, "ok").await
+        self.proxy_persist(c, &nms, "ok").await
     }
     pub async fn proxy_stop(&self, c: hbtp::Context) -> io::Result<()> {
         let nms = if let Some(vs) = c.get_arg("name") {
@@ -335,7 +679,7 @@ impl ServerCase {
             return c.res_string(hbtp::ResCodeOk, "param name err").await;
         };
         self.inner.proxy.stop(&nms).await?;
-        c.res_string(hbtp::ResCodeOk, "ok").await
+        self.proxy_persist(c, &nms, "ok").await
     }
     pub async fn proxy_remove(&self, c: hbtp::Context) -> io::Result<()> {
         let nms = if let Some(vs) = c.get_arg("name") {
@@ -343,7 +687,129 @@ impl ServerCase {
         } else {
             return c.res_string(hbtp::ResCodeOk, "param name err").await;
         };
-        self.inner.proxy.remove(&nms).await;
+        if let Err(e) = self.inner.proxy.remove(&nms).await {
+            log::error!("proxy {} remove save err:{}", nms.as_str(), e);
+            return c
+                .res_string(hbtp::ResCodeErr, format!("removed,but save conf err:{}", e).as_str())
+                .await;
+        }
+        c.res_string(hbtp::ResCodeOk, "ok").await
+    }
+    pub async fn proxy_acl(&self, c: hbtp::Context) -> io::Result<()> {
+        let nms = match c.get_arg("name") {
+            None => return c.res_string(hbtp::ResCodeErr, "param err:name").await,
+            Some(v) => v,
+        };
+        let data: ProxyAclReq = c.body_json()?;
+        let rule = match self.inner.proxy.get_rule(&nms).await {
+            None => return c.res_string(hbtp::ResCodeNotFound, "Not found rule").await,
+            Some(v) => v,
+        };
+        if let Err(e) = rule.set_acl(data.allow, data.deny) {
+            return c.res_string(hbtp::ResCodeErr, format!("{}", e).as_str()).await;
+        }
+        self.proxy_persist(c, &nms, "ok").await
+    }
+    // the change is live already,a save err is reported so it isn't lost silently on restart
+    async fn proxy_persist(&self, c: hbtp::Context, name: &String, oks: &str) -> io::Result<()> {
+        if let Err(e) = self.inner.proxy.persist(name).await {
+            log::error!("proxy {} save err:{}", name.as_str(), e);
+            return c
+                .res_string(hbtp::ResCodeErr, format!("applied,but save conf err:{}", e).as_str())
+                .await;
+        }
+        c.res_string(hbtp::ResCodeOk, oks).await
+    }
+
+    pub async fn conn_list(&self, c: hbtp::Context) -> io::Result<()> {
+        let rule = c.get_arg("rule").filter(|v| !v.is_empty());
+        let rts = ConnListRep {
+            list: self.inner.node.conn_list(rule.as_deref()),
+        };
+        c.res_json(hbtp::ResCodeOk, &rts).await
+    }
+    pub async fn conn_kill(&self, c: hbtp::Context) -> io::Result<()> {
+        let ids = match c.get_arg("id") {
+            None => return c.res_string(hbtp::ResCodeErr, "param err:id").await,
+            Some(v) => v,
+        };
+        if self.inner.node.conn_kill(ids.as_str()) {
+            c.res_string(hbtp::ResCodeOk, "ok").await
+        } else {
+            c.res_string(hbtp::ResCodeNotFound, "Not found conn").await
+        }
+    }
+
+    pub async fn budget_list(&self, c: hbtp::Context) -> io::Result<()> {
+        let mut rts = BudgetListRep { list: Vec::new() };
+        rts.list.push(BudgetListIt {
+            scope: "server".to_string(),
+            name: String::new(),
+            limit: self.inner.node.budget().limit(),
+        });
+        for (k, v) in self.inner.node.node_budgets() {
+            rts.list.push(BudgetListIt {
+                scope: "node".to_string(),
+                name: k,
+                limit: Some(v),
+            });
+        }
+        for v in self.inner.proxy.rules().await {
+            if let Some(lmt) = v.budget_limit() {
+                rts.list.push(BudgetListIt {
+                    scope: "rule".to_string(),
+                    name: v.conf().name.clone(),
+                    limit: Some(lmt),
+                });
+            }
+        }
+        c.res_json(hbtp::ResCodeOk, &rts).await
+    }
+    /// up and down are kb/s,both 0 or missing clears the budget
+    pub async fn budget_set(&self, c: hbtp::Context) -> io::Result<()> {
+        let mut lmt = ProxyLimit {
+            up: 0,
+            down: 0,
+            burst: None,
+        };
+        for (k, v) in [("up", &mut lmt.up), ("down", &mut lmt.down)] {
+            if let Some(vs) = c.get_arg(k) {
+                match vs.parse::<usize>() {
+                    Err(_) => {
+                        let errs = format!("param err:{}", k);
+                        return c.res_string(hbtp::ResCodeErr, errs.as_str()).await;
+                    }
+                    Ok(n) => *v = n,
+                }
+            }
+        }
+        if let Some(vs) = c.get_arg("burst") {
+            match vs.parse::<usize>() {
+                Err(_) => return c.res_string(hbtp::ResCodeErr, "param err:burst").await,
+                Ok(n) => lmt.burst = Some(n).filter(|v| *v > 0),
+            }
+        }
+        let limit = if lmt.up == 0 && lmt.down == 0 {
+            None
+        } else {
+            Some(lmt)
+        };
+        let name = c.get_arg("name").unwrap_or_default();
+        match c.get_arg("scope").as_deref() {
+            Some("server") => self.inner.node.budget().set(limit),
+            Some("node") if !name.is_empty() => {
+                self.inner.node.node_budget(&name).set(limit);
+            }
+            Some("rule") => {
+                let rule = match self.inner.proxy.get_rule(&name).await {
+                    None => return c.res_string(hbtp::ResCodeNotFound, "Not found rule").await,
+                    Some(v) => v,
+                };
+                rule.set_budget(limit);
+                return self.proxy_persist(c, &name, "ok").await;
+            }
+            _ => return c.res_string(hbtp::ResCodeErr, "param err:scope or name").await,
+        }
         c.res_string(hbtp::ResCodeOk, "ok").await
     }
 }
@@ -0,0 +1,432 @@
+use std::{io, time::Duration};
+
+use async_std::net::TcpStream;
+use futures::AsyncReadExt;
+
+const HTTP_HEAD_MAX: usize = 1024 * 16;
+const SNIFF_TIMEOUT: Duration = Duration::from_secs(10);
+const TLS_HELLO_MAX: usize = 1024 * 64;
+
+/// read until the end of the http request head,the bytes read must be replayed to the backend.
+pub async fn read_http_head(conn: &mut TcpStream) -> io::Result<Box<[u8]>> {
+    let mut bts = Vec::new();
+    let mut buf = vec![0u8; 4096];
+    while bts.len() < HTTP_HEAD_MAX {
+        let n = async_std::io::timeout(SNIFF_TIMEOUT, conn.read(&mut buf)).await?;
+        if n == 0 {
+            return Err(ruisutil::ioerr("read size=0", None));
+        }
+        let start = bts.len().saturating_sub(3);
+        bts.extend_from_slice(&buf[..n]);
+        if bts[start..].windows(4).any(|v| v == b"\r\n\r\n") {
+            return Ok(bts.into_boxed_slice());
+        }
+    }
+    Err(ruisutil::ioerr("http head out limit!!", None))
+}
+
+/// host(lowercase,without port) and path of the first request in bts.
+pub fn parse_http_head(bts: &[u8]) -> Option<(String, String)> {
+    let end = bts.windows(4).position(|v| v == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&bts[..end]).ok()?;
+    let mut lines = head.split("\r\n");
+    let reqs: Vec<&str> = lines.next()?.split(' ').collect();
+    if reqs.len() != 3 || !reqs[2].starts_with("HTTP/") {
+        return None;
+    }
+    let path = reqs[1].to_string();
+    for ln in lines {
+        let (k, v) = match ln.find(':') {
+            None => continue,
+            Some(i) => (&ln[..i], ln[i + 1..].trim()),
+        };
+        if k.eq_ignore_ascii_case("host") {
+            return Some((host_name(v), path));
+        }
+    }
+    None
+}
+
+/// the head in bts rewritten with `Connection: close`,the backend ends the conn after
+/// this request so the next one is routed again;an upgrade keeps the conn as it is.
+/// bytes past the first request's body are pipelined requests,they are dropped
+/// rather than sent to a backend routed for another request.
+pub fn http_close(bts: &[u8]) -> Box<[u8]> {
+    let end = match bts.windows(4).position(|v| v == b"\r\n\r\n") {
+        None => return bts.into(),
+        Some(v) => v,
+    };
+    let head = match std::str::from_utf8(&bts[..end]) {
+        Err(_) => return bts.into(),
+        Ok(v) => v,
+    };
+    let mut rts = String::with_capacity(end + 20);
+    let (mut clen, mut chunked) = (Some(0), false);
+    for (i, ln) in head.split("\r\n").enumerate() {
+        if i > 0 {
+            let (k, v) = match ln.find(':') {
+                None => (ln, ""),
+                Some(i) => (&ln[..i], ln[i + 1..].trim()),
+            };
+            if k.eq_ignore_ascii_case("content-length") {
+                clen = v.parse::<usize>().ok();
+            }
+            if k.eq_ignore_ascii_case("transfer-encoding") {
+                chunked = v.to_ascii_lowercase().contains("chunked");
+            }
+            if k.eq_ignore_ascii_case("connection") {
+                if v.split(',').any(|v| v.trim().eq_ignore_ascii_case("upgrade")) {
+                    return bts.into();
+                }
+                continue;
+            }
+            if k.eq_ignore_ascii_case("keep-alive") || k.eq_ignore_ascii_case("proxy-connection") {
+                continue;
+            }
+        }
+        rts.push_str(ln);
+        rts.push_str("\r\n");
+    }
+    rts.push_str("Connection: close\r\n\r\n");
+    let body = &bts[end + 4..];
+    // a body not read in full yet has no pipelined bytes after it
+    let ln = match (chunked, clen) {
+        (true, _) => chunked_len(body),
+        (false, Some(v)) => Some(v),
+        (false, None) => None,
+    };
+    let body = match ln {
+        Some(n) if n < body.len() => {
+            log::debug!("http pipelined bytes dropped:{}", body.len() - n);
+            &body[..n]
+        }
+        _ => body,
+    };
+    let mut rts = rts.into_bytes();
+    rts.extend_from_slice(body);
+    rts.into_boxed_slice()
+}
+// the length of a whole chunked body at the start of bts,None while it is not all here
+fn chunked_len(bts: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    loop {
+        let ln = bts[pos..].windows(2).position(|v| v == b"\r\n")?;
+        let sizes = std::str::from_utf8(&bts[pos..pos + ln]).ok()?;
+        let sizes = sizes.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(sizes, 16).ok()?;
+        pos += ln + 2;
+        if size == 0 {
+            // trailers,then the empty line
+            if bts[pos..].starts_with(b"\r\n") {
+                return Some(pos + 2);
+            }
+            let ln = bts[pos..].windows(4).position(|v| v == b"\r\n\r\n")?;
+            return Some(pos + ln + 4);
+        }
+        pos = pos.checked_add(size)?.checked_add(2)?;
+        if pos > bts.len() {
+            return None;
+        }
+    }
+}
+
+/// prefix matches whole path segments,/api takes /api,/api/v1 and /api?a=1 but not /apix.
+pub fn path_prefix(prefix: &str, path: &str) -> bool {
+    if !path.starts_with(prefix) {
+        return false;
+    }
+    if prefix.is_empty() || prefix.ends_with('/') {
+        return true;
+    }
+    matches!(path.as_bytes().get(prefix.len()), None | Some(b'/') | Some(b'?') | Some(b'#'))
+}
+
+fn host_name(host: &str) -> String {
+    let host = if host.starts_with('[') {
+        match host.find(']') {
+            None => host,
+            Some(i) => &host[..i + 1],
+        }
+    } else {
+        match host.rfind(':') {
+            None => host,
+            Some(i) => &host[..i],
+        }
+    };
+    host.to_ascii_lowercase()
+}
+
+/// read the tls records holding the ClientHello,a hello may be fragmented over records.
+pub async fn read_tls_hello(conn: &mut TcpStream) -> io::Result<Box<[u8]>> {
+    let mut bts = Vec::new();
+    loop {
+        let start = bts.len();
+        bts.resize(start + 5, 0);
+        async_std::io::timeout(SNIFF_TIMEOUT, conn.read_exact(&mut bts[start..])).await?;
+        if bts[start] != 0x16 {
+            return Err(ruisutil::ioerr("not tls handshake", None));
+        }
+        let ln = ((bts[start + 3] as usize) << 8) | (bts[start + 4] as usize);
+        if bts.len() + ln > TLS_HELLO_MAX {
+            return Err(ruisutil::ioerr("tls hello out limit!!", None));
+        }
+        bts.resize(start + 5 + ln, 0);
+        async_std::io::timeout(SNIFF_TIMEOUT, conn.read_exact(&mut bts[start + 5..])).await?;
+        let hs = handshake(&bts).unwrap_or_default();
+        if hs.len() >= 4 && hs.len() >= 4 + hello_len(&hs) {
+            return Ok(bts.into_boxed_slice());
+        }
+    }
+}
+// the handshake bytes of the records in bts joined,None on a record of another type
+fn handshake(bts: &[u8]) -> Option<Vec<u8>> {
+    let mut rd = Reader { bts, pos: 0 };
+    let mut rts = Vec::new();
+    while rd.pos < rd.bts.len() {
+        if rd.u8()? != 0x16 {
+            return None;
+        }
+        rd.skip(2)?;
+        rts.extend_from_slice(rd.vec16()?);
+    }
+    Some(rts)
+}
+fn hello_len(hs: &[u8]) -> usize {
+    ((hs[1] as usize) << 16) | ((hs[2] as usize) << 8) | (hs[3] as usize)
+}
+
+/// server name of the ClientHello in the tls records,lowercase.
+pub fn parse_sni(bts: &[u8]) -> Option<String> {
+    let hs = handshake(bts)?;
+    let mut rd = Reader { bts: &hs, pos: 0 };
+    if rd.u8()? != 0x01 {
+        return None;
+    }
+    let ln = ((rd.u8()? as usize) << 16) | rd.u16()?;
+    let mut rd = Reader {
+        bts: rd.take(ln)?,
+        pos: 0,
+    };
+    rd.skip(2 + 32)?; // version,random
+    let ln = rd.u8()? as usize;
+    rd.skip(ln)?; // session id
+    let ln = rd.u16()?;
+    rd.skip(ln)?; // cipher suites
+    let ln = rd.u8()? as usize;
+    rd.skip(ln)?; // compression methods
+    let mut exts = Reader {
+        bts: rd.vec16()?,
+        pos: 0,
+    };
+    while exts.pos < exts.bts.len() {
+        let tp = exts.u16()?;
+        let data = exts.vec16()?;
+        if tp != 0 {
+            continue;
+        }
+        let mut rd = Reader { bts: data, pos: 0 };
+        let mut names = Reader {
+            bts: rd.vec16()?,
+            pos: 0,
+        };
+        while names.pos < names.bts.len() {
+            let tp = names.u8()?;
+            let name = names.vec16()?;
+            if tp == 0 {
+                return std::str::from_utf8(name).ok().map(|v| v.to_ascii_lowercase());
+            }
+        }
+    }
+    None
+}
+
+struct Reader<'a> {
+    bts: &'a [u8],
+    pos: usize,
+}
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.pos + n > self.bts.len() {
+            return None;
+        }
+        let rts = &self.bts[self.pos..self.pos + n];
+        self.pos += n;
+        Some(rts)
+    }
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.take(n).map(|_| ())
+    }
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|v| v[0])
+    }
+    fn u16(&mut self) -> Option<usize> {
+        self.take(2).map(|v| ((v[0] as usize) << 8) | (v[1] as usize))
+    }
+    // a u16 length prefixed vector
+    fn vec16(&mut self) -> Option<&'a [u8]> {
+        let n = self.u16()?;
+        self.take(n)
+    }
+}
+
+pub async fn http_error(conn: &mut TcpStream, code: i32, msg: &str) {
+    http_reply(conn, code, msg, "text/plain", msg).await
+}
+pub async fn http_reply(conn: &mut TcpStream, code: i32, msg: &str, ctype: &str, body: &str) {
+    let res = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        code,
+        msg,
+        ctype,
+        body.len(),
+        body
+    );
+    if let Err(e) = futures::AsyncWriteExt::write_all(conn, res.as_bytes()).await {
+        log::debug!("http_reply write err:{}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::{
+        net::{TcpListener, TcpStream},
+        task,
+    };
+
+    use super::{http_close, parse_http_head, parse_sni, path_prefix, read_tls_hello};
+
+    #[test]
+    fn http_heads() {
+        let bts = b"GET /api/v1?a=1 HTTP/1.1\r\nHOST: App.Example.com:8080\r\nAccept: */*\r\n\r\nbody";
+        assert_eq!(
+            parse_http_head(bts),
+            Some(("app.example.com".to_string(), "/api/v1?a=1".to_string()))
+        );
+        let bts = b"GET / HTTP/1.1\r\nHost: [::1]:80\r\n\r\n";
+        assert_eq!(parse_http_head(bts), Some(("[::1]".to_string(), "/".to_string())));
+        assert_eq!(parse_http_head(b"GET / HTTP/1.1\r\nHost: a\r\n"), None);
+        assert_eq!(parse_http_head(b"GET / HTTP/1.1\r\n\r\n"), None);
+        assert_eq!(parse_http_head(b"\x16\x03\x01\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn http_closes() {
+        let bts = b"POST / HTTP/1.1\r\nHost: a\r\nConnection: keep-alive\r\nKeep-Alive: 5\r\n\
+                    Content-Length: 4\r\n\r\nbody";
+        assert_eq!(
+            &http_close(bts)[..],
+            &b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\nConnection: close\r\n\r\nbody"[..]
+        );
+        let bts = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+        assert_eq!(
+            &http_close(bts)[..],
+            &b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n"[..]
+        );
+        let bts = b"GET /ws HTTP/1.1\r\nHost: a\r\nConnection: keep-alive, Upgrade\r\n\r\n";
+        assert_eq!(&http_close(bts)[..], &bts[..]);
+    }
+
+    #[test]
+    fn http_pipelines() {
+        let closes = b"Connection: close\r\n\r\n";
+        let bts = b"GET /a HTTP/1.1\r\nHost: a\r\n\r\nGET /b HTTP/1.1\r\nHost: b\r\n\r\n";
+        let rts = http_close(bts);
+        assert!(rts.ends_with(closes), "{:?}", String::from_utf8_lossy(&rts));
+        let bts = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nabcGET / HTTP/1.1\r\n";
+        assert!(http_close(bts).ends_with(b"close\r\n\r\nabc"));
+        // not all the body is here yet,it goes on as it is
+        let bts = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 9\r\n\r\nabc";
+        assert!(http_close(bts).ends_with(b"close\r\n\r\nabc"));
+        let body = b"3\r\nabc\r\n0\r\n\r\n";
+        let mut bts = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+        bts.extend_from_slice(body);
+        let rts = http_close(&bts);
+        bts.extend_from_slice(b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(http_close(&bts), rts);
+        assert!(rts.ends_with(body));
+        let bts = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nab";
+        assert!(http_close(bts).ends_with(b"close\r\n\r\n5\r\nab"));
+    }
+
+    #[test]
+    fn path_prefixs() {
+        assert!(path_prefix("", "/apix"));
+        assert!(path_prefix("/", "/apix"));
+        assert!(path_prefix("/api", "/api"));
+        assert!(path_prefix("/api", "/api/v1"));
+        assert!(path_prefix("/api", "/api?a=1"));
+        assert!(path_prefix("/api/", "/api/v1"));
+        assert!(!path_prefix("/api", "/apix"));
+        assert!(!path_prefix("/api/", "/api"));
+        assert!(!path_prefix("/api", "/ap"));
+    }
+
+    fn client_hello(name: &str) -> Vec<u8> {
+        let mut sni = vec![0u8];
+        sni.extend_from_slice(&(name.len() as u16).to_be_bytes());
+        sni.extend_from_slice(name.as_bytes());
+        let mut ext = (sni.len() as u16).to_be_bytes().to_vec();
+        ext.extend_from_slice(&sni);
+        let mut exts = vec![0x00, 0x0b, 0x00, 0x02, 0x01, 0x00]; // ec_point_formats
+        exts.extend_from_slice(&[0x00, 0x00]);
+        exts.extend_from_slice(&(ext.len() as u16).to_be_bytes());
+        exts.extend_from_slice(&ext);
+        let mut hello = vec![0x03, 0x03];
+        hello.extend_from_slice(&[7u8; 32]);
+        hello.extend_from_slice(&[0x00, 0x00, 0x02, 0x13, 0x01, 0x01, 0x00]);
+        hello.extend_from_slice(&(exts.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&exts);
+        let mut hs = vec![0x01, 0x00];
+        hs.extend_from_slice(&(hello.len() as u16).to_be_bytes());
+        hs.extend_from_slice(&hello);
+        let mut rec = vec![0x16, 0x03, 0x01];
+        rec.extend_from_slice(&(hs.len() as u16).to_be_bytes());
+        rec.extend_from_slice(&hs);
+        rec
+    }
+
+    #[test]
+    fn tls_snis() {
+        let bts = client_hello("Web.Example.com");
+        assert_eq!(parse_sni(&bts), Some("web.example.com".to_string()));
+        assert_eq!(parse_sni(&bts[..bts.len() - 3]), None);
+        assert_eq!(parse_sni(b"GET / HTTP/1.1\r\n\r\n"), None);
+    }
+
+    // the handshake of one record split over records of n bytes
+    fn fragment(rec: &[u8], n: usize) -> Vec<u8> {
+        let mut rts = Vec::new();
+        for v in rec[5..].chunks(n) {
+            rts.extend_from_slice(&rec[..3]);
+            rts.extend_from_slice(&(v.len() as u16).to_be_bytes());
+            rts.extend_from_slice(v);
+        }
+        rts
+    }
+
+    #[test]
+    fn tls_fragments() {
+        let bts = fragment(&client_hello("web.example.com"), 16);
+        assert_eq!(parse_sni(&bts), Some("web.example.com".to_string()));
+        assert_eq!(parse_sni(&bts[..bts.len() - 3]), None);
+        let mut bts = bts;
+        bts[5 + 16] = 0x17;
+        assert_eq!(parse_sni(&bts), None);
+    }
+
+    #[test]
+    fn tls_reads() {
+        task::block_on(async {
+            let lsr = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let mut cli = TcpStream::connect(lsr.local_addr().unwrap()).await.unwrap();
+            let (mut conn, _) = lsr.accept().await.unwrap();
+            let hello = fragment(&client_hello("web.example.com"), 7);
+            let mut bts = hello.clone();
+            bts.extend_from_slice(b"\x17\x03\x03\x00\x01a");
+            futures::AsyncWriteExt::write_all(&mut cli, &bts).await.unwrap();
+            let rts = read_tls_hello(&mut conn).await.unwrap();
+            assert_eq!(&rts[..], &hello[..]);
+            assert_eq!(parse_sni(&rts), Some("web.example.com".to_string()));
+        });
+    }
+}
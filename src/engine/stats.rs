@@ -0,0 +1,77 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use crate::entity::util::TrafficInfo;
+
+/// live counters of a rule or a node,conns add to every stat they go through.
+#[derive(Default)]
+pub struct TrafficStat {
+    up: AtomicU64,
+    down: AtomicU64,
+    active: AtomicU64,
+    total: AtomicU64,
+    dial_fails: AtomicU64,
+    rejects: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl TrafficStat {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+    pub fn dial_fail(&self) {
+        self.dial_fails.fetch_add(1, Ordering::Relaxed);
+    }
+    /// a conn refused by a cap
+    pub fn reject(&self) {
+        self.rejects.fetch_add(1, Ordering::Relaxed);
+    }
+    /// a conn no route of the rule takes
+    pub fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn info(&self) -> TrafficInfo {
+        TrafficInfo {
+            up: self.up.load(Ordering::Relaxed),
+            down: self.down.load(Ordering::Relaxed),
+            active: self.active.load(Ordering::Relaxed),
+            total: self.total.load(Ordering::Relaxed),
+            dial_fails: self.dial_fails.load(Ordering::Relaxed),
+            rejects: self.rejects.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// one conn on its stats,counted active until dropped
+pub struct TrafficConn {
+    stats: Vec<Arc<TrafficStat>>,
+}
+impl TrafficConn {
+    pub fn new(stats: Vec<Arc<TrafficStat>>) -> Self {
+        for v in &stats {
+            v.active.fetch_add(1, Ordering::Relaxed);
+            v.total.fetch_add(1, Ordering::Relaxed);
+        }
+        Self { stats }
+    }
+    pub fn up(&self, n: usize) {
+        for v in &self.stats {
+            v.up.fetch_add(n as u64, Ordering::Relaxed);
+        }
+    }
+    pub fn down(&self, n: usize) {
+        for v in &self.stats {
+            v.down.fetch_add(n as u64, Ordering::Relaxed);
+        }
+    }
+}
+impl Drop for TrafficConn {
+    fn drop(&mut self) {
+        for v in &self.stats {
+            v.active.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
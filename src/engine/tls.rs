@@ -0,0 +1,302 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io,
+    io::BufReader,
+    net::Shutdown,
+    sync::{Arc, Mutex as StdMutex},
+    time::Duration,
+};
+
+use async_std::{
+    net::{TcpListener, TcpStream},
+    task,
+};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, StreamExt};
+use futures_rustls::{
+    rustls::{pki_types::ServerName, ClientConfig, RootCertStore, ServerConfig},
+    TlsAcceptor, TlsConnector,
+};
+use once_cell::sync::Lazy;
+
+use crate::{app::Application, utils::sign};
+
+// the real peer of each front conn,by the loopback addr the hbtp engine sees it from
+static PEERS: Lazy<StdMutex<HashMap<String, String>>> = Lazy::new(|| StdMutex::new(HashMap::new()));
+// TlsCheck answers are signed with it,only the engine of this process knows it
+static CHECK_KEY: Lazy<String> = Lazy::new(|| ruisutil::random(32));
+
+pub fn load_acceptor(cert: &str, key: &str) -> io::Result<TlsAcceptor> {
+    let mut rd = BufReader::new(File::open(cert)?);
+    let certs = match rustls_pemfile::certs(&mut rd).collect::<Result<Vec<_>, _>>() {
+        Err(_) => return Err(ruisutil::ioerr(format!("tls cert({}) err", cert), None)),
+        Ok(v) => v,
+    };
+    let keys = match rustls_pemfile::private_key(&mut BufReader::new(File::open(key)?)) {
+        Err(_) => return Err(ruisutil::ioerr(format!("tls key({}) err", key), None)),
+        Ok(v) => v,
+    };
+    let keys = match keys {
+        Some(v) if !certs.is_empty() => v,
+        _ => return Err(ruisutil::ioerr("tls cert or key is empty", None)),
+    };
+    let cfg = match ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, keys)
+    {
+        Err(e) => return Err(ruisutil::ioerr(format!("tls cert err:{}", e), None)),
+        Ok(v) => v,
+    };
+    Ok(TlsAcceptor::from(Arc::new(cfg)))
+}
+
+/// only the given ca is trusted,so the server cert is pinned to it.
+pub fn load_connector(ca: &str) -> io::Result<TlsConnector> {
+    let mut roots = RootCertStore::empty();
+    let mut rd = BufReader::new(File::open(ca)?);
+    for v in rustls_pemfile::certs(&mut rd) {
+        let added = match v {
+            Err(_) => false,
+            Ok(v) => roots.add(v).is_ok(),
+        };
+        if !added {
+            return Err(ruisutil::ioerr(format!("tls ca({}) err", ca), None));
+        }
+    }
+    if roots.is_empty() {
+        return Err(ruisutil::ioerr(format!("tls ca({}) is empty", ca), None));
+    }
+    let cfg = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Ok(TlsConnector::from(Arc::new(cfg)))
+}
+
+/// a free loopback addr for the hbtp engine behind a TlsFront,
+/// the front checks the engine on it is ours before it forwards anything.
+pub fn local_addr() -> io::Result<String> {
+    let lsr = std::net::TcpListener::bind("127.0.0.1:0")?;
+    Ok(lsr.local_addr()?.to_string())
+}
+
+/// the addr a conn really came from,a front conn is looked up by its loopback addr
+pub fn real_addr(addrs: String) -> String {
+    match PEERS.lock() {
+        Err(_) => addrs,
+        Ok(lkv) => match lkv.get(&addrs) {
+            None => addrs,
+            Some(v) => v.clone(),
+        },
+    }
+}
+pub fn peer_addr(c: &hbtp::Context) -> io::Result<String> {
+    Ok(real_addr(c.peer_addr()?))
+}
+/// a conn the front carried,an engine behind a front refuses the rest since they skip tls
+pub fn fronted(c: &hbtp::Context) -> bool {
+    match (c.peer_addr(), PEERS.lock()) {
+        (Ok(v), Ok(lkv)) => lkv.contains_key(&v),
+        _ => false,
+    }
+}
+/// TlsCheck: the nonce signed with this process's key
+pub async fn check_res(c: hbtp::Context) -> io::Result<()> {
+    let nonce = c.get_arg("nonce").unwrap_or_default();
+    let signs = sign::hmac_sign(CHECK_KEY.as_str(), nonce.as_str());
+    c.res_string(hbtp::ResCodeOk, signs.as_str()).await
+}
+
+async fn pipe<S: AsyncRead + AsyncWrite + Unpin>(stream: S, conn: TcpStream) {
+    let (mut rd, mut wr) = stream.split();
+    let mut connr = conn.clone();
+    let mut connw = conn.clone();
+    let up = async {
+        if let Err(e) = futures::io::copy(&mut rd, &mut connw).await {
+            log::debug!("tls pipe up err:{}", e);
+        }
+        let _ = conn.shutdown(Shutdown::Write);
+    };
+    let down = async {
+        if let Err(e) = futures::io::copy(&mut connr, &mut wr).await {
+            log::debug!("tls pipe down err:{}", e);
+        }
+        let _ = wr.close().await;
+    };
+    futures::future::join(up, down).await;
+    let _ = conn.shutdown(Shutdown::Both);
+}
+
+/// terminates tls on `addrs` and hands the plaintext to the hbtp engine on `locals`.
+pub struct TlsFront {
+    ctx: ruisutil::Context,
+    addrs: String,
+    locals: String,
+    acceptor: TlsAcceptor,
+}
+impl TlsFront {
+    pub fn new(
+        ctx: ruisutil::Context,
+        addrs: String,
+        locals: String,
+        acceptor: TlsAcceptor,
+    ) -> Self {
+        Self {
+            ctx,
+            addrs,
+            locals,
+            acceptor,
+        }
+    }
+    /// the engine on locals answers TlsCheck with our key,
+    /// else another process took the port and must not get the plaintext
+    async fn check_local(&self) -> io::Result<()> {
+        let nonce = ruisutil::random(20);
+        let signs = sign::hmac_sign(CHECK_KEY.as_str(), nonce.as_str());
+        for _ in 0..50 {
+            if self.ctx.done() {
+                break;
+            }
+            let mut req = hbtp::Request::new(self.locals.as_str(), 1);
+            req.command("TlsCheck");
+            req.add_arg("nonce", nonce.as_str());
+            // not up yet,try again
+            let res = match req.do_string(None, "").await {
+                Err(_) => {
+                    task::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+                Ok(v) => v,
+            };
+            let bodys = res.get_bodys().map(|v| &v[..]).unwrap_or_default();
+            if res.get_code() == hbtp::ResCodeOk && bodys == signs.as_bytes() {
+                return Ok(());
+            }
+            break;
+        }
+        Err(ruisutil::ioerr(
+            format!(
+                "tls front local {} is not this server",
+                self.locals.as_str()
+            ),
+            None,
+        ))
+    }
+    pub async fn run(&self) -> io::Result<()> {
+        let lsr = TcpListener::bind(self.addrs.as_str()).await?;
+        self.check_local().await?;
+        let mut incom = lsr.incoming();
+        while !self.ctx.done() {
+            match incom.next().await {
+                None => break,
+                Some(Err(e)) => {
+                    log::error!("tls front conn err:{}", e);
+                    break;
+                }
+                Some(Ok(conn)) => {
+                    let acceptor = self.acceptor.clone();
+                    let locals = self.locals.clone();
+                    task::spawn(async move {
+                        let addr = conn.peer_addr();
+                        let stream = match acceptor.accept(conn).await {
+                            Err(e) => {
+                                log::debug!("tls accept {:?} err:{}", addr, e);
+                                return;
+                            }
+                            Ok(v) => v,
+                        };
+                        let connlc = match TcpStream::connect(locals.as_str()).await {
+                            Err(e) => {
+                                log::error!("tls front connect {} err:{}", locals.as_str(), e);
+                                return;
+                            }
+                            Ok(v) => v,
+                        };
+                        // the engine sees the loopback addr,keep the real one for it
+                        let keys = match (connlc.local_addr(), addr) {
+                            (Ok(lc), Ok(v)) => {
+                                let keys = lc.to_string();
+                                if let Ok(mut lkv) = PEERS.lock() {
+                                    lkv.insert(keys.clone(), v.to_string());
+                                }
+                                Some(keys)
+                            }
+                            _ => None,
+                        };
+                        pipe(stream, connlc).await;
+                        if let (Some(v), Ok(mut lkv)) = (keys, PEERS.lock()) {
+                            lkv.remove(&v);
+                        }
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// client side: a loopback listener whose conns are carried to the server over tls,
+/// so hbtp requests can keep dialing a plain addr.
+pub struct TlsTunnel {
+    is_api: bool,
+    name: Option<String>,
+    connector: TlsConnector,
+}
+impl TlsTunnel {
+    pub fn new(is_api: bool, name: Option<String>, connector: TlsConnector) -> Self {
+        Self {
+            is_api,
+            name,
+            connector,
+        }
+    }
+    pub async fn start(self, ctx: ruisutil::Context) -> io::Result<String> {
+        let lsr = TcpListener::bind("127.0.0.1:0").await?;
+        let locals = lsr.local_addr()?.to_string();
+        let tn = Arc::new(self);
+        task::spawn(async move {
+            let mut incom = lsr.incoming();
+            while !ctx.done() {
+                match incom.next().await {
+                    None => break,
+                    Some(Err(e)) => {
+                        log::error!("tls tunnel conn err:{}", e);
+                        break;
+                    }
+                    Some(Ok(conn)) => {
+                        let tn = tn.clone();
+                        task::spawn(async move {
+                            if let Err(e) = tn.run_conn(conn).await {
+                                log::error!("tls tunnel err:{}", e);
+                            }
+                        });
+                    }
+                }
+            }
+        });
+        Ok(locals)
+    }
+    async fn run_conn(&self, conn: TcpStream) -> io::Result<()> {
+        let addrs = if self.is_api {
+            Application::get().apiaddrs.clone()
+        } else {
+            Application::get().addrs.clone()
+        };
+        let name = match &self.name {
+            Some(v) => v.clone(),
+            None => match addrs.rfind(":") {
+                None => addrs.clone(),
+                Some(i) => addrs[..i]
+                    .trim_matches(|c| c == '[' || c == ']')
+                    .to_string(),
+            },
+        };
+        let connrm = TcpStream::connect(addrs.as_str()).await?;
+        let name = match ServerName::try_from(name) {
+            Err(e) => return Err(ruisutil::ioerr(format!("tls name err:{}", e), None)),
+            Ok(v) => v,
+        };
+        let stream = self.connector.connect(name, connrm).await?;
+        pipe(stream, conn).await;
+        Ok(())
+    }
+}
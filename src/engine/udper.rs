@@ -0,0 +1,277 @@
+use std::{io, net::Shutdown, net::SocketAddr, sync::Arc, time::Duration};
+
+use async_std::{
+    channel::Receiver,
+    future,
+    net::{ToSocketAddrs, UdpSocket},
+    task,
+};
+use ruisutil::ArcMut;
+
+use super::{
+    conn::ProxyConn,
+    stats::{TrafficConn, TrafficStat},
+};
+
+pub const UDP_PACKET_MAX: usize = 65535;
+pub const UDP_QUEUE_MAX: usize = 128;
+const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// datagrams are carried over the node tcp conn as: [len:u16 BE][bytes],
+// len=0 is an empty datagram
+pub async fn read_packet(ctx: &ruisutil::Context, conn: &mut ProxyConn) -> io::Result<Box<[u8]>> {
+    let bts = conn.read_exact(ctx, 2).await?;
+    let ln = ((bts[0] as usize) << 8) | (bts[1] as usize);
+    if ln == 0 {
+        return Ok(Box::new([]));
+    }
+    conn.read_exact(ctx, ln).await
+}
+pub async fn write_packet(
+    ctx: &ruisutil::Context,
+    conn: &mut ProxyConn,
+    bts: &[u8],
+) -> io::Result<()> {
+    if bts.len() > UDP_PACKET_MAX {
+        return Err(ruisutil::ioerr("packet out limit!!", None));
+    }
+    let mut buf = Vec::with_capacity(bts.len() + 2);
+    buf.push((bts.len() >> 8) as u8);
+    buf.push(bts.len() as u8);
+    buf.extend_from_slice(bts);
+    conn.write_all(ctx, &buf[..]).await
+}
+
+pub async fn connect(addrs: &str) -> io::Result<UdpSocket> {
+    let addr = match addrs.to_socket_addrs().await?.next() {
+        None => return Err(ruisutil::ioerr("udp addr not found", None)),
+        Some(v) => v,
+    };
+    let sock = if addr.is_ipv6() {
+        UdpSocket::bind("[::]:0").await?
+    } else {
+        UdpSocket::bind("0.0.0.0:0").await?
+    };
+    sock.connect(addr).await?;
+    Ok(sock)
+}
+
+/// node side: relay between the server conn and a connected local udp socket.
+#[derive(Clone)]
+pub struct Udper {
+    inner: ArcMut<Inner>,
+}
+struct Inner {
+    ctx: ruisutil::Context,
+    ids: String,
+    conn: ProxyConn,
+    sock: UdpSocket,
+    ctmout: ruisutil::Timer,
+}
+impl Udper {
+    pub fn new(ctx: ruisutil::Context, ids: String, conn: ProxyConn, sock: UdpSocket) -> Self {
+        Self {
+            inner: ArcMut::new(Inner {
+                ctx: ruisutil::Context::background(Some(ctx)),
+                ids,
+                conn,
+                sock,
+                ctmout: ruisutil::Timer::new(UDP_IDLE_TIMEOUT),
+            }),
+        }
+    }
+    fn stop(&self) {
+        self.inner.ctx.stop();
+        if let Err(e) = self.inner.conn.shutdown(Shutdown::Both) {
+            log::debug!("Udper stop conn.shutdown err:{}", e);
+        }
+    }
+    pub async fn start(self) {
+        log::debug!("Udper({}) start", self.inner.ids.as_str());
+        self.inner.ctmout.reset();
+        let wg = ruisutil::WaitGroup::new();
+        let c = self.clone();
+        let wgc = wg.clone();
+        task::spawn(async move {
+            let _wgc = wgc;
+            if let Err(e) = c.read_conn().await {
+                log::debug!("Udper({}) read_conn err:{}", c.inner.ids.as_str(), e);
+            }
+            c.stop();
+        });
+        let c = self.clone();
+        let wgc = wg.clone();
+        task::spawn(async move {
+            let _wgc = wgc;
+            if let Err(e) = c.read_sock().await {
+                log::debug!("Udper({}) read_sock err:{}", c.inner.ids.as_str(), e);
+            }
+            c.stop();
+        });
+        wg.waits().await;
+        log::debug!("Udper({}) end", self.inner.ids.as_str());
+    }
+    async fn read_conn(&self) -> io::Result<()> {
+        let ins = unsafe { self.inner.muts() };
+        while !self.inner.ctx.done() {
+            let bts = read_packet(&self.inner.ctx, &mut ins.conn).await?;
+            self.inner.ctmout.reset();
+            self.inner.sock.send(&bts).await?;
+        }
+        Ok(())
+    }
+    async fn read_sock(&self) -> io::Result<()> {
+        let ins = unsafe { self.inner.muts() };
+        let mut buf = vec![0u8; UDP_PACKET_MAX].into_boxed_slice();
+        while !self.inner.ctx.done() {
+            let n = match async_std::io::timeout(Duration::from_secs(1), self.inner.sock.recv(&mut buf)).await {
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    if self.inner.ctmout.tmout() {
+                        return Err(ruisutil::ioerr("udp idle timeout", None));
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+                Ok(v) => v,
+            };
+            self.inner.ctmout.reset();
+            write_packet(&self.inner.ctx, &mut ins.conn, &buf[..n]).await?;
+        }
+        Ok(())
+    }
+}
+
+/// server side: one client address of a udp rule, relayed over its own node conn.
+#[derive(Clone)]
+pub struct UdpSession {
+    inner: ArcMut<SessInner>,
+}
+struct SessInner {
+    ctx: ruisutil::Context,
+    ids: String,
+    conn: ProxyConn,
+    lsr: Arc<UdpSocket>,
+    addr: SocketAddr,
+    rcv: Receiver<Box<[u8]>>,
+    ctmout: ruisutil::Timer,
+    traffic: TrafficConn,
+}
+impl UdpSession {
+    pub fn new(
+        ctx: ruisutil::Context,
+        ids: String,
+        conn: ProxyConn,
+        lsr: Arc<UdpSocket>,
+        addr: SocketAddr,
+        rcv: Receiver<Box<[u8]>>,
+        stats: Vec<Arc<TrafficStat>>,
+    ) -> Self {
+        Self {
+            inner: ArcMut::new(SessInner {
+                ctx: ruisutil::Context::background(Some(ctx)),
+                ids,
+                conn,
+                lsr,
+                addr,
+                rcv,
+                ctmout: ruisutil::Timer::new(UDP_IDLE_TIMEOUT),
+                traffic: TrafficConn::new(stats),
+            }),
+        }
+    }
+    fn stop(&self) {
+        self.inner.ctx.stop();
+        self.inner.rcv.close();
+        if let Err(e) = self.inner.conn.shutdown(Shutdown::Both) {
+            log::debug!("UdpSession stop conn.shutdown err:{}", e);
+        }
+    }
+    pub async fn start(self) {
+        log::debug!(
+            "UdpSession({}) start from:{}",
+            self.inner.ids.as_str(),
+            self.inner.addr
+        );
+        self.inner.ctmout.reset();
+        let wg = ruisutil::WaitGroup::new();
+        let c = self.clone();
+        let wgc = wg.clone();
+        task::spawn(async move {
+            let _wgc = wgc;
+            if let Err(e) = c.read_conn().await {
+                log::debug!("UdpSession({}) read_conn err:{}", c.inner.ids.as_str(), e);
+            }
+            c.stop();
+        });
+        let c = self.clone();
+        let wgc = wg.clone();
+        task::spawn(async move {
+            let _wgc = wgc;
+            if let Err(e) = c.write_conn().await {
+                log::debug!("UdpSession({}) write_conn err:{}", c.inner.ids.as_str(), e);
+            }
+            c.stop();
+        });
+        wg.waits().await;
+        log::debug!(
+            "UdpSession({}) end from:{}",
+            self.inner.ids.as_str(),
+            self.inner.addr
+        );
+    }
+    async fn read_conn(&self) -> io::Result<()> {
+        let ins = unsafe { self.inner.muts() };
+        while !self.inner.ctx.done() {
+            let bts = read_packet(&self.inner.ctx, &mut ins.conn).await?;
+            self.inner.ctmout.reset();
+            self.inner.traffic.down(bts.len());
+            self.inner.lsr.send_to(&bts, self.inner.addr).await?;
+        }
+        Ok(())
+    }
+    async fn write_conn(&self) -> io::Result<()> {
+        let ins = unsafe { self.inner.muts() };
+        while !self.inner.ctx.done() {
+            let bts = match future::timeout(Duration::from_secs(1), self.inner.rcv.recv()).await {
+                Err(_) => {
+                    if self.inner.ctmout.tmout() {
+                        return Err(ruisutil::ioerr("udp idle timeout", None));
+                    }
+                    continue;
+                }
+                Ok(Err(_)) => return Err(ruisutil::ioerr("udp session closed", None)),
+                Ok(Ok(v)) => v,
+            };
+            self.inner.ctmout.reset();
+            self.inner.traffic.up(bts.len());
+            write_packet(&self.inner.ctx, &mut ins.conn, &bts).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use async_std::{
+        net::{TcpListener, TcpStream},
+        task,
+    };
+
+    use super::{read_packet, write_packet};
+    use crate::engine::conn::ProxyConn;
+
+    #[test]
+    fn empty_packets() {
+        task::block_on(async {
+            let lsr = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let conn = TcpStream::connect(lsr.local_addr().unwrap()).await.unwrap();
+            let (accs, _) = lsr.accept().await.unwrap();
+            let (mut conn, mut accs) = (ProxyConn::Tcp(conn), ProxyConn::Tcp(accs));
+            let ctx = ruisutil::Context::background(None);
+            write_packet(&ctx, &mut conn, b"").await.unwrap();
+            write_packet(&ctx, &mut conn, b"abc").await.unwrap();
+            assert!(read_packet(&ctx, &mut accs).await.unwrap().is_empty());
+            assert_eq!(&read_packet(&ctx, &mut accs).await.unwrap()[..], b"abc");
+        });
+    }
+}
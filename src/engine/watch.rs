@@ -0,0 +1,84 @@
+use std::io;
+
+use async_std::channel::Sender;
+
+// yml files only,the temp files of a save are skipped
+#[cfg(target_os = "linux")]
+fn is_conf_file(name: &str) -> bool {
+    name.ends_with(".yml") || name.ends_with(".yaml")
+}
+
+/// send the names of conf files created,changed or deleted in dir,until the receiver is dropped.
+#[cfg(target_os = "linux")]
+pub fn watch_dir(dir: &str, sdr: Sender<String>) -> io::Result<()> {
+    let dirs = match std::ffi::CString::new(dir) {
+        Err(_) => return Err(ruisutil::ioerr("watch dir err", None)),
+        Ok(v) => v,
+    };
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mask = libc::IN_CLOSE_WRITE
+        | libc::IN_MOVED_TO
+        | libc::IN_MOVED_FROM
+        | libc::IN_DELETE
+        | libc::IN_CREATE;
+    if unsafe { libc::inotify_add_watch(fd, dirs.as_ptr(), mask) } < 0 {
+        let e = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(e);
+    }
+    std::thread::spawn(move || {
+        let mut buf = vec![0u8; 4096];
+        let mut pfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // poll with a timeout,the thread ends soon after the receiver is gone
+        while !sdr.is_closed() {
+            if unsafe { libc::poll(&mut pfd, 1, 1000) } <= 0 {
+                continue;
+            }
+            let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if n <= 0 {
+                continue;
+            }
+            for name in parse_events(&buf[..n as usize]) {
+                if is_conf_file(name.as_str()) {
+                    let _ = sdr.try_send(name);
+                }
+            }
+        }
+        unsafe { libc::close(fd) };
+        log::debug!("proxys watch end");
+    });
+    Ok(())
+}
+#[cfg(not(target_os = "linux"))]
+pub fn watch_dir(_: &str, _: Sender<String>) -> io::Result<()> {
+    Err(ruisutil::ioerr("proxys watch is only on linux", None))
+}
+
+// inotify_event: wd i32,mask u32,cookie u32,len u32,name[len]
+#[cfg(target_os = "linux")]
+fn parse_events(bts: &[u8]) -> Vec<String> {
+    let mut rts = Vec::new();
+    let mut pos = 0;
+    while pos + 16 <= bts.len() {
+        let ln = u32::from_ne_bytes([bts[pos + 12], bts[pos + 13], bts[pos + 14], bts[pos + 15]]);
+        let ln = ln as usize;
+        let end = (pos + 16 + ln).min(bts.len());
+        let name = &bts[pos + 16..end];
+        let name = match name.iter().position(|v| *v == 0) {
+            None => name,
+            Some(i) => &name[..i],
+        };
+        if !name.is_empty() {
+            rts.push(String::from_utf8_lossy(name).to_string());
+        }
+        pos += 16 + ln;
+    }
+    rts
+}
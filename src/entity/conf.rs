@@ -2,7 +2,10 @@ use std::io;
 
 use serde::{Deserialize, Serialize};
 
-use super::{node::ProxyGoto, util::ProxyLimit};
+use super::{
+    node::{ProxyGoto, RuleRoute},
+    util::ProxyLimit,
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct ServerConf {
@@ -17,29 +20,116 @@ pub struct ServerInfoConf {
     pub key: Option<String>,
     pub log_path: Option<String>,
     pub proxys_path: Option<String>,
+    // reload by itself when files in proxys_path change(linux)
+    pub proxys_watch: Option<bool>,
+    // legacy signs only,a hmac sign always has its times checked
     pub key_time_check: Option<bool>,
+    pub sign_legacy: Option<bool>,
+    pub nodes: Option<Vec<NodeAuthConf>>,
+    pub nodes_path: Option<String>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub tls_ca: Option<String>,
+    pub tls_name: Option<String>,
+    // plain http listener of /metrics and /healthz,like 127.0.0.1:6575
+    pub metrics_host: Option<String>,
+    // kb/s shared by all conns of the server
+    pub budget: Option<ProxyLimit>,
+    // concurrent tunnels to each node
+    pub node_max_tunnels: Option<u32>,
+}
+/// a node name(or a pattern like `edge-*`) and the key it signs requests with
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NodeAuthConf {
+    pub name: String,
+    pub key: String,
+    // kb/s shared by all conns to the node
+    pub budget: Option<ProxyLimit>,
+    // concurrent tunnels to the node,over server.node_max_tunnels
+    pub max_tunnels: Option<u32>,
 }
 #[derive(Serialize, Deserialize)]
 pub struct ApiServerInfoConf {
     // #[serde(rename = "name")]
     pub host: Option<String>,
     pub key: Option<String>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub tls_ca: Option<String>,
+    pub tls_name: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ProxyInfoConf {
     // #[serde(rename = "name")]
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub stop: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<String>,
+    // empty bind: no listener,only reachable by node visitors
     pub bind: String,
+    // node visitors must give it,a rule without one can't be visited
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visit_key: Option<String>,
+    // tcp(def),http: route each conn by Host and path,tls-sni: by the ClientHello server name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    // failover(def),round-robin,random,least-conn,source-hash
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balance: Option<String>,
+    // probe the gotos by their nodes,down gotos are skipped
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<bool>,
+    // kb/s shared by all conns of the rule
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub budget: Option<ProxyLimit>,
+    // concurrent conns,concurrent conns of a client ip,new conns per second
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_conns: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_conns_per_ip: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conn_rate: Option<u32>,
+    // client cidrs like 10.0.0.0/8 or fd00::/8,deny wins
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deny: Option<Vec<String>>,
+    #[serde(default)]
+    pub proxys: Vec<ProxyInfoGoto>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub routes: Option<Vec<ProxyInfoRoute>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProxyInfoRoute {
+    // may be a pattern like *.example.com
+    pub host: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
     pub proxys: Vec<ProxyInfoGoto>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ProxyInfoGoto {
     pub proxy: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub localhost: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub limit: Option<ProxyLimit>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+    // v1 or v2: tell the target the real client addr
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_protocol: Option<String>,
+    // deflate the node tunnel,for text traffic over slow links
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compress: Option<bool>,
+    // seal the node tunnel,needs a node key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encrypt: Option<bool>,
 }
 
 impl ProxyInfoConf {
@@ -51,6 +141,19 @@ impl ProxyInfoConf {
         Ok(ls)
     }
 }
+impl ProxyInfoRoute {
+    pub fn conv_route(&self) -> io::Result<RuleRoute> {
+        let mut ls = Vec::new();
+        for v in &self.proxys {
+            ls.push(v.conv_proxy_goto()?);
+        }
+        Ok(RuleRoute {
+            host: self.host.to_ascii_lowercase(),
+            path: self.path.clone(),
+            goto: ls,
+        })
+    }
+}
 impl ProxyInfoGoto {
     pub fn conv_proxy_goto(&self) -> io::Result<ProxyGoto> {
         let gotols: Vec<&str> = self.proxy.split(":").collect();
@@ -65,6 +168,11 @@ impl ProxyInfoGoto {
         } else {
             return Err(ruisutil::ioerr("goto port err", None));
         };
+        if let Some(vs) = &self.proxy_protocol {
+            if vs != "v1" && vs != "v2" {
+                return Err(ruisutil::ioerr(format!("proxy_protocol err:{}", vs), None));
+            }
+        }
         Ok(ProxyGoto {
             proxy_host: if gotols[0].is_empty() {
                 "localhost".to_string()
@@ -74,6 +182,10 @@ impl ProxyInfoGoto {
             proxy_port: gotoport,
             localhost: self.localhost.clone(),
             limit: self.limit.clone(),
+            weight: self.weight,
+            proxy_protocol: self.proxy_protocol.clone(),
+            compress: self.compress,
+            encrypt: self.encrypt,
         })
     }
 }
@@ -86,7 +198,18 @@ impl Default for ServerConf {
                 key: None,
                 log_path: None,
                 proxys_path: None,
+                proxys_watch: None,
                 key_time_check: None,
+                sign_legacy: None,
+                nodes: None,
+                nodes_path: None,
+                tls_cert: None,
+                tls_key: None,
+                tls_ca: None,
+                tls_name: None,
+                metrics_host: None,
+                budget: None,
+                node_max_tunnels: None,
             },
             api_server: None,
         }
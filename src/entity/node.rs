@@ -1,16 +1,20 @@
 use serde::{Deserialize, Serialize};
 
-use super::util::ProxyLimit;
+use super::util::{ProxyLimit, TrafficInfo};
 
 #[derive(Serialize, Deserialize)]
 pub struct RegNodeReq {
     pub name: String,
     pub token: Option<String>,
     pub version: Option<String>,
+    pub mux: Option<bool>,
+    // the node answers NODE_CTRL_PROBE
+    pub probe: Option<bool>,
 }
 #[derive(Serialize, Deserialize)]
 pub struct RegNodeRep {
     pub token: String,
+    pub mux: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -21,6 +25,7 @@ pub struct NodeListIt {
     pub online: bool,
     pub online_times: u64,
     pub outline_times: Option<u64>,
+    pub traffic: Option<TrafficInfo>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -35,6 +40,29 @@ pub struct NodeConnMsg {
     // pub token: String,
     pub host: Option<String>,
     pub port: i32,
+    pub protocol: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_proto: Option<ProxyProtoMsg>,
+    // the server asks,the node answers with compress=1 when it deflates too
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compress: Option<bool>,
+    // the same for encrypt,keyed by the node key and xids
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypt: Option<bool>,
+}
+/// a PROXY protocol header for the local conn,src and dst are the client and listener addrs
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ProxyProtoMsg {
+    pub version: String,
+    pub src: Option<String>,
+    pub dst: Option<String>,
+}
+
+/// a visitor conn opened by a node,routed to the gotos of the rule
+#[derive(Serialize, Deserialize)]
+pub struct NodeVisitMsg {
+    pub rule: String,
+    pub key: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -44,9 +72,25 @@ pub struct ProxyGotoReq {
     pub name: Option<String>,
 }
 #[derive(Clone, Serialize, Deserialize)]
+pub struct RuleRoute {
+    pub host: String,
+    pub path: Option<String>,
+    pub goto: Vec<ProxyGoto>,
+}
+#[derive(Clone, Serialize, Deserialize)]
 pub struct ProxyGoto {
     pub proxy_host: String,
     pub proxy_port: i32,
     pub localhost: Option<String>,
     pub limit: Option<ProxyLimit>,
+    pub weight: Option<u32>,
+    // v1 or v2,sent to the target before the client bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_protocol: Option<String>,
+    // deflate the tunnel to the node,if the node can
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compress: Option<bool>,
+    // seal the tunnel with a key from the node credential,a node that can't is not used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypt: Option<bool>,
 }
@@ -1,13 +1,51 @@
 use serde::{Deserialize, Serialize};
 
-use super::{node::ProxyGoto, util::ProxyLimit};
+use super::{
+    conf::ProxyInfoRoute,
+    node::{ProxyGoto, RuleRoute},
+    util::{ProxyLimit, TrafficInfo},
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct RuleConfReq {
     pub name: Option<String>,
+    pub protocol: Option<String>,
     pub bind_host: String,
     pub bind_port: i32,
     pub goto: Vec<RuleConfGoto>,
+    pub visit_key: Option<String>,
+    pub health_check: Option<bool>,
+    #[serde(default)]
+    pub allow: Option<Vec<String>>,
+    #[serde(default)]
+    pub deny: Option<Vec<String>>,
+    // tcp(def),http,tls-sni,as mode of the yml rules
+    #[serde(default)]
+    pub mode: Option<String>,
+    #[serde(default)]
+    pub routes: Option<Vec<ProxyInfoRoute>>,
+    // failover(def),round-robin,random,least-conn,source-hash
+    #[serde(default)]
+    pub balance: Option<String>,
+    // kb/s shared by all conns of the rule
+    #[serde(default)]
+    pub budget: Option<ProxyLimit>,
+    // concurrent conns,concurrent conns of a client ip,new conns per second,0 is no cap
+    #[serde(default)]
+    pub max_conns: Option<u32>,
+    #[serde(default)]
+    pub max_conns_per_ip: Option<u32>,
+    #[serde(default)]
+    pub conn_rate: Option<u32>,
+}
+
+/// the client cidrs of a rule,both empty lets all in
+#[derive(Serialize, Deserialize)]
+pub struct ProxyAclReq {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub deny: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -15,16 +53,41 @@ pub struct RuleConfGoto {
     pub proxy_host: String,
     pub proxy_port: i32,
     pub limit: Option<ProxyLimit>,
+    pub weight: Option<u32>,
+    #[serde(default)]
+    pub proxy_protocol: Option<String>,
+    #[serde(default)]
+    pub compress: Option<bool>,
+    #[serde(default)]
+    pub encrypt: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ProxyListIt {
     pub name: String,
     pub remote: String,
+    pub protocol: Option<String>,
+    pub mode: Option<String>,
     // pub proxy:String,
     pub goto: Vec<ProxyGoto>,
+    pub routes: Option<Vec<RuleRoute>>,
     pub status: i32,
     pub msg: Option<String>,
+    pub health: Option<Vec<ProxyGotoHealth>>,
+    pub traffic: Option<TrafficInfo>,
+    #[serde(default)]
+    pub allow: Option<Vec<String>>,
+    #[serde(default)]
+    pub deny: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProxyGotoHealth {
+    pub goto: String,
+    // None: not probed,the node is too old
+    pub up: Option<bool>,
+    pub fails: u32,
+    pub msg: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -32,17 +95,86 @@ pub struct ProxyListRep {
     pub list: Vec<ProxyListIt>,
 }
 
+/// rule names touched by a reload,a file with errs keeps its old rules
+#[derive(Default, Serialize, Deserialize)]
+pub struct ProxyReloadRep {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+    pub errs: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ConnListRep {
+    pub list: Vec<ConnListIt>,
+}
+/// a conn being relayed,times is seconds since it started
+#[derive(Serialize, Deserialize)]
+pub struct ConnListIt {
+    pub id: String,
+    pub rule: Option<String>,
+    pub goto: String,
+    pub client: Option<String>,
+    pub times: u64,
+    pub up: u64,
+    pub down: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BudgetListRep {
+    pub list: Vec<BudgetListIt>,
+}
+/// scope: server,node or rule
+#[derive(Serialize, Deserialize)]
+pub struct BudgetListIt {
+    pub scope: String,
+    pub name: String,
+    pub limit: Option<ProxyLimit>,
+}
+
 impl ProxyListIt {
+    pub fn bindstr(&self) -> String {
+        match (&self.protocol, &self.mode) {
+            (_, Some(vs)) if vs != "tcp" => format!("{}/{}", self.remote.as_str(), vs.as_str()),
+            (Some(vs), _) if vs != "tcp" => format!("{}/{}", self.remote.as_str(), vs.as_str()),
+            _ => self.remote.clone(),
+        }
+    }
     pub fn proxystr(&self) -> String {
-        let mut rts = Vec::new();
-        for v in &self.goto {
-            // rts+=format!("{}:{},",v.proxy_host,v.proxy_port)
-            let lcls = match &v.localhost {
-                Some(vs) => format!("({})",vs),
-                None => "".to_string(),
-            };
-            rts.push(format!("{}{}:{}", v.proxy_host, lcls, v.proxy_port));
+        let mut rts = vec![gotostr(&self.goto)];
+        if let Some(vs) = &self.routes {
+            for v in vs {
+                rts.push(format!(
+                    "{}{}->{}",
+                    v.host.as_str(),
+                    v.path.as_deref().unwrap_or(""),
+                    gotostr(&v.goto)
+                ));
+            }
+        }
+        rts.retain(|v| !v.is_empty());
+        rts.join(" ")
+    }
+    /// up/probed gotos,"-" if the rule has no health check
+    pub fn healthstr(&self) -> String {
+        match &self.health {
+            Some(vs) if !vs.is_empty() => {
+                let ups = vs.iter().filter(|v| v.up != Some(false)).count();
+                format!("{}/{}", ups, vs.len())
+            }
+            _ => "-".to_string(),
         }
-        rts.join(",")
     }
 }
+fn gotostr(gotos: &Vec<ProxyGoto>) -> String {
+    let mut rts = Vec::new();
+    for v in gotos {
+        // rts+=format!("{}:{},",v.proxy_host,v.proxy_port)
+        let lcls = match &v.localhost {
+            Some(vs) => format!("({})",vs),
+            None => "".to_string(),
+        };
+        rts.push(format!("{}{}:{}", v.proxy_host, lcls, v.proxy_port));
+    }
+    rts.join(",")
+}
@@ -1,7 +1,44 @@
-use serde::{Deserialize, Serialize};
-
-#[derive(Clone,Serialize, Deserialize)]
-pub struct ProxyLimit {
-  pub up: usize,   // kb/s
-  pub down: usize, // kb/s
-}
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone,Serialize, Deserialize)]
+pub struct ProxyLimit {
+  pub up: usize,   // kb/s
+  pub down: usize, // kb/s
+  // kb sent at once after idle,def a second of the rate
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub burst: Option<usize>,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct TrafficInfo {
+  pub up: u64,   // bytes client->target
+  pub down: u64, // bytes target->client
+  pub active: u64,
+  pub total: u64,
+  pub dial_fails: u64,
+  #[serde(default)]
+  pub rejects: u64,
+  #[serde(default)]
+  pub misses: u64,
+}
+impl TrafficInfo {
+  pub fn trafficstr(&self) -> String {
+    let mut rts = format!(
+      "{}/{} {}/{}",
+      crate::utils::mybytes(self.up),
+      crate::utils::mybytes(self.down),
+      self.active,
+      self.total
+    );
+    if self.dial_fails > 0 {
+      rts += format!(" fail:{}", self.dial_fails).as_str();
+    }
+    if self.rejects > 0 {
+      rts += format!(" rej:{}", self.rejects).as_str();
+    }
+    if self.misses > 0 {
+      rts += format!(" miss:{}", self.misses).as_str();
+    }
+    rts
+  }
+}
@@ -2,12 +2,14 @@ extern crate async_std;
 extern crate clap;
 extern crate flexi_logger;
 extern crate futures;
+extern crate futures_rustls;
 extern crate hbtp;
 extern crate hex;
 extern crate libc;
 extern crate log;
 extern crate once_cell;
 extern crate ruisutil;
+extern crate rustls_pemfile;
 extern crate serde;
 extern crate serde_json;
 extern crate serde_yaml;
@@ -130,6 +132,31 @@ fn main() {
                             Arg::with_name("keys")
                                 .value_name("KEY")
                                 .help("join to server by key"),
+                        )
+                        .arg(
+                            Arg::with_name("mux")
+                                .long("mux")
+                                .help("proxy conns over the node control conn"),
+                        )
+                        .arg(
+                            Arg::with_name("visit")
+                                .long("visit")
+                                .value_name("BIND=RULE")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("listen on this node and proxy to a server rule(example:127.0.0.1:5432=pg)"),
+                        )
+                        .arg(
+                            Arg::with_name("visit-key")
+                                .long("visit-key")
+                                .value_name("KEY")
+                                .help("key for the visited rules"),
+                        )
+                        .arg(
+                            Arg::with_name("pool")
+                                .long("pool")
+                                .value_name("N")
+                                .help("idle data conns kept on the server,not with mux(def:0)"),
                         ),
                 )
                 .subcommand(SubCommand::with_name("ls").about("node list")),
@@ -158,6 +185,129 @@ fn main() {
                                 .long("name")
                                 .value_name("NAME")
                                 .help("proxy rule name"),
+                        )
+                        .arg(
+                            Arg::with_name("protocol")
+                                .long("protocol")
+                                .value_name("PROTOCOL")
+                                .possible_values(&["tcp", "udp"])
+                                .help("proxy rule protocol(def:tcp)"),
+                        )
+                        .arg(
+                            Arg::with_name("mode")
+                                .long("mode")
+                                .value_name("MODE")
+                                .possible_values(&["tcp", "http", "tls-sni"])
+                                .help(
+                                    "route conns by http Host or tls server name(def:tcp),\
+                                     http takes one request per conn",
+                                ),
+                        )
+                        .arg(
+                            Arg::with_name("balance")
+                                .long("balance")
+                                .value_name("BALANCE")
+                                .possible_values(&[
+                                    "failover",
+                                    "round-robin",
+                                    "random",
+                                    "least-conn",
+                                    "source-hash",
+                                ])
+                                .help("how the gotos share conns(def:failover)"),
+                        )
+                        .arg(
+                            Arg::with_name("visit-key")
+                                .long("visit-key")
+                                .value_name("KEY")
+                                .help("key node visitors must give to reach this rule,no key no visitors"),
+                        )
+                        .arg(
+                            Arg::with_name("health")
+                                .long("health")
+                                .help("probe the gotos,down gotos are skipped"),
+                        )
+                        .arg(
+                            Arg::with_name("compress")
+                                .long("compress")
+                                .help("deflate the tunnels to the nodes"),
+                        )
+                        .arg(
+                            Arg::with_name("encrypt")
+                                .long("encrypt")
+                                .help("seal the tunnels to the nodes with the node key"),
+                        )
+                        .arg(
+                            Arg::with_name("allow")
+                                .long("allow")
+                                .value_name("CIDR")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("client ip or net let in,like 10.0.0.0/8"),
+                        )
+                        .arg(
+                            Arg::with_name("deny")
+                                .long("deny")
+                                .value_name("CIDR")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("client ip or net kept out,wins over allow"),
+                        )
+                        .arg(
+                            Arg::with_name("max-conns")
+                                .long("max-conns")
+                                .value_name("N")
+                                .help("concurrent conns(def:0,no cap)"),
+                        )
+                        .arg(
+                            Arg::with_name("max-conns-per-ip")
+                                .long("max-conns-per-ip")
+                                .value_name("N")
+                                .help("concurrent conns of a client ip,a udp client is one conn"),
+                        )
+                        .arg(
+                            Arg::with_name("conn-rate")
+                                .long("conn-rate")
+                                .value_name("N")
+                                .help("new conns per second"),
+                        )
+                        .arg(
+                            Arg::with_name("budget-up")
+                                .long("budget-up")
+                                .value_name("KB/S")
+                                .help("client to target,shared by all conns of the rule"),
+                        )
+                        .arg(
+                            Arg::with_name("budget-down")
+                                .long("budget-down")
+                                .value_name("KB/S")
+                                .help("target to client,shared by all conns of the rule"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("acl")
+                        .about("set the client ip lists,none lets all in")
+                        .arg(
+                            Arg::with_name("name")
+                                .required(true)
+                                .value_name("NAME")
+                                .help("proxy name"),
+                        )
+                        .arg(
+                            Arg::with_name("allow")
+                                .long("allow")
+                                .value_name("CIDR")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("client ip or net let in,like 10.0.0.0/8"),
+                        )
+                        .arg(
+                            Arg::with_name("deny")
+                                .long("deny")
+                                .value_name("CIDR")
+                                .multiple(true)
+                                .number_of_values(1)
+                                .help("client ip or net kept out,wins over allow"),
                         ),
                 )
                 .subcommand(SubCommand::with_name("ls").about("proxy list"))
@@ -186,6 +336,65 @@ fn main() {
                     ),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("conn")
+                .about("conn command")
+                .subcommand(
+                    SubCommand::with_name("ls").about("conn list").arg(
+                        Arg::with_name("rule")
+                            .long("rule")
+                            .value_name("NAME")
+                            .help("only the conns of this proxy"),
+                    ),
+                )
+                .subcommand(
+                    SubCommand::with_name("kill").about("close a conn").arg(
+                        Arg::with_name("id")
+                            .required(true)
+                            .value_name("ID")
+                            .help("conn id"),
+                    ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("budget")
+                .about("bandwidth shared by the conns of a scope")
+                .subcommand(SubCommand::with_name("ls").about("budget list"))
+                .subcommand(
+                    SubCommand::with_name("set")
+                        .about("set a budget,no --up and --down clears it")
+                        .arg(
+                            Arg::with_name("scope")
+                                .required(true)
+                                .possible_values(&["server", "node", "rule"])
+                                .value_name("SCOPE")
+                                .help("server,node or rule"),
+                        )
+                        .arg(
+                            Arg::with_name("name")
+                                .value_name("NAME")
+                                .help("node or rule name"),
+                        )
+                        .arg(
+                            Arg::with_name("up")
+                                .long("up")
+                                .value_name("KB/S")
+                                .help("client to target"),
+                        )
+                        .arg(
+                            Arg::with_name("down")
+                                .long("down")
+                                .value_name("KB/S")
+                                .help("target to client"),
+                        )
+                        .arg(
+                            Arg::with_name("burst")
+                                .long("burst")
+                                .value_name("KB")
+                                .help("sent at once after idle,def a second of the rate"),
+                        ),
+                ),
+        )
         .get_matches();
 
     let conf: Option<crate::entity::conf::ServerConf> =
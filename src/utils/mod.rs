@@ -1,8 +1,13 @@
 pub mod msg;
+mod notify;
+pub mod sign;
 mod util;
 
+pub use notify::{Notify, NOTIFY_RECHECK};
+
 pub use util::{
-    compare_version, envs, host_defport, mytimes, remote_version, ymlfile, CompareVersion,
+    compare_version, envs, host_defport, mybytes, mytimes, name_match, remote_version, ymlfile,
+    ymlfile_save, CompareVersion, IpAcl,
 };
 
 pub const HBTP_TOKEN_ERR: i32 = 100;
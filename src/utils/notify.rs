@@ -0,0 +1,66 @@
+use std::time::Duration;
+
+use async_std::channel::{self, Receiver, Sender};
+
+/// the longest a waiter sleeps before it looks at its ctx again,
+/// a parent ctx stop wakes no one
+pub const NOTIFY_RECHECK: Duration = Duration::from_secs(1);
+
+/// wakes the task waiting on it,a notify with no one waiting is kept for the next wait
+/// so one sent between the check and the wait is not lost. close wakes every waiter for good
+#[derive(Clone)]
+pub struct Notify {
+    tx: Sender<()>,
+    rx: Receiver<()>,
+}
+
+impl Notify {
+    pub fn new() -> Self {
+        let (tx, rx) = channel::bounded(1);
+        Self { tx, rx }
+    }
+    pub fn notify(&self) {
+        let _ = self.tx.try_send(());
+    }
+    pub fn close(&self) {
+        self.tx.close();
+    }
+    /// true when notified,false on closed or after tmout
+    pub async fn wait(&self, tmout: Duration) -> bool {
+        matches!(
+            async_std::future::timeout(tmout, self.rx.recv()).await,
+            Ok(Ok(()))
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Notify;
+
+    #[test]
+    fn notifies() {
+        async_std::task::block_on(async {
+            let nt = Notify::new();
+            // kept until someone waits,many notifies wake once
+            nt.notify();
+            nt.notify();
+            assert!(nt.wait(Duration::from_secs(1)).await);
+            assert!(!nt.wait(Duration::from_millis(20)).await);
+
+            let c = nt.clone();
+            let h = async_std::task::spawn(async move { c.wait(Duration::from_secs(5)).await });
+            async_std::task::sleep(Duration::from_millis(20)).await;
+            nt.notify();
+            assert!(h.await);
+
+            let tms = Instant::now();
+            nt.close();
+            assert!(!nt.wait(Duration::from_secs(5)).await);
+            assert!(!nt.wait(Duration::from_secs(5)).await);
+            assert!(tms.elapsed() < Duration::from_secs(1));
+        });
+    }
+}
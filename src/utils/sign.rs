@@ -0,0 +1,124 @@
+use std::time::{Duration, SystemTime};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+pub const SIGN_VER: &str = "2";
+// how far the request times may be from the server clock,either way
+pub const SIGN_WINDOW: Duration = Duration::from_secs(120);
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// the signed text: cmd\ntimes\nrandom\nsign_args\nargs\nhex(sha256(body)),
+/// args are sorted by key so both sides agree on the order.
+/// every field,key and value is length prefixed as len:text,so no arg can be re-split
+/// into others and the sign_args list the server reads the args by is signed too.
+pub fn sign_texts(
+    cmds: &str,
+    tms: &str,
+    rands: &str,
+    keys: &str,
+    args: &[(&str, &str)],
+    bodys: Option<&[u8]>,
+) -> String {
+    let mut args = args.to_vec();
+    args.sort();
+    let argls: Vec<String> = args.iter().map(|(k, v)| format!("{}={}", lp(k), lp(v))).collect();
+    let bodyhs = hex::encode(Sha256::digest(bodys.unwrap_or(&[])));
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        lp(cmds),
+        lp(tms),
+        lp(rands),
+        lp(keys),
+        argls.join("&"),
+        bodyhs.as_str()
+    )
+}
+fn lp(s: &str) -> String {
+    format!("{}:{}", s.len(), s)
+}
+
+pub fn hmac_sign(key: &str, texts: &str) -> String {
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("hmac key");
+    mac.update(texts.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// a 32 byte key from a credential,texts name what the key is for
+pub fn derive_key(key: &str, texts: &str) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("hmac key");
+    mac.update(texts.as_bytes());
+    mac.finalize().into_bytes().into()
+}
+
+pub fn in_window(now: SystemTime, tms: SystemTime) -> bool {
+    match now.duration_since(tms) {
+        Ok(v) => v <= SIGN_WINDOW,
+        Err(e) => e.duration() <= SIGN_WINDOW,
+    }
+}
+
+/// takes the same time wherever the two differ
+pub fn same_secret(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |rt, (x, y)| rt | (x ^ y)) == 0
+}
+
+pub fn hmac_check(key: &str, texts: &str, signs: &str) -> bool {
+    let bts = match hex::decode(signs) {
+        Err(_) => return false,
+        Ok(v) => v,
+    };
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("hmac key");
+    mac.update(texts.as_bytes());
+    mac.verify_slice(&bts[..]).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_args_order() {
+        let s1 = sign_texts("c", "t", "r", "b,a", &[("b", "2"), ("a", "1")], Some(b"x"));
+        let s2 = sign_texts("c", "t", "r", "b,a", &[("a", "1"), ("b", "2")], Some(b"x"));
+        assert_eq!(s1, s2);
+        let signs = hmac_sign("key", s1.as_str());
+        assert!(hmac_check("key", s2.as_str(), signs.as_str()));
+        assert!(!hmac_check("key2", s2.as_str(), signs.as_str()));
+        let s3 = sign_texts("c", "t", "r", "b,a", &[("a", "1"), ("b", "2")], Some(b"y"));
+        assert!(!hmac_check("key", s3.as_str(), signs.as_str()));
+        assert!(same_secret("key", "key"));
+        assert!(!same_secret("key", "kex"));
+        assert!(!same_secret("key", "key2"));
+    }
+
+    #[test]
+    fn resplit_args() {
+        let texts = sign_texts("c", "t", "r", "a,b", &[("a", "1"), ("b", "2")], None);
+        let signs = hmac_sign("key", texts.as_str());
+        // what check_sign rebuilds when sign_args or the values are re-split
+        let s1 = sign_texts("c", "t", "r", "a", &[("a", "1&b=2")], None);
+        let s2 = sign_texts("c", "t", "r", "a,b", &[("a", "1&b"), ("b", "2")], None);
+        let s3 = sign_texts("c", "t", "r", "a", &[("a", "1"), ("b", "2")], None);
+        for v in [s1, s2, s3] {
+            assert!(!hmac_check("key", v.as_str(), signs.as_str()), "{}", v);
+        }
+        assert!(hmac_check("key", texts.as_str(), signs.as_str()));
+    }
+
+    #[test]
+    fn windows() {
+        let now = SystemTime::now();
+        let secs = |n| Duration::from_secs(n);
+        assert!(in_window(now, now));
+        assert!(in_window(now, now - secs(119)));
+        assert!(in_window(now, now + secs(119)));
+        assert!(!in_window(now, now - secs(121)));
+        assert!(!in_window(now, now + secs(121)));
+    }
+}
@@ -1,6 +1,6 @@
-use std::{env, io, path::Path};
+use std::{env, io, net::IpAddr, path::Path};
 
-use serde::de::DeserializeOwned;
+use serde::{de::DeserializeOwned, Serialize};
 
 pub async fn remote_version(mut req: hbtp::Request) -> io::Result<String> {
     req.command("version");
@@ -39,6 +39,23 @@ pub fn ymlfile<T: DeserializeOwned, P: AsRef<Path>>(path: P) -> io::Result<T> {
     }
 }
 
+/// write to a temp file beside path and rename it over,readers never see a half written file.
+pub fn ymlfile_save<T: Serialize, P: AsRef<Path>>(path: P, data: &T) -> io::Result<()> {
+    let path = path.as_ref();
+    let bts = match serde_yaml::to_string(data) {
+        Err(e) => return Err(ruisutil::ioerr(format!("yml err:{}", e), None)),
+        Ok(v) => v,
+    };
+    let mut tmps = path.as_os_str().to_owned();
+    tmps.push(".tmp");
+    {
+        let mut fl = std::fs::File::create(&tmps)?;
+        io::Write::write_all(&mut fl, bts.as_bytes())?;
+        fl.sync_all()?;
+    }
+    std::fs::rename(&tmps, path)
+}
+
 pub fn mytimes(sec: u64) -> String {
     if sec >= 86400 {
         let day = sec / 86400;
@@ -60,6 +77,20 @@ pub fn mytimes(sec: u64) -> String {
     }
 }
 
+pub fn mybytes(n: u64) -> String {
+    let units = ["K", "M", "G", "T"];
+    if n < 1024 {
+        return format!("{}B", n);
+    }
+    let mut v = n as f64 / 1024.0;
+    let mut i = 0;
+    while v >= 1024.0 && i < units.len() - 1 {
+        v /= 1024.0;
+        i += 1;
+    }
+    format!("{:.1}{}", v, units[i])
+}
+
 pub enum CompareVersion {
     Err,
     Eq,
@@ -105,3 +136,155 @@ pub fn host_defport(host: String, defport: i32) -> String {
         format!("{}:{}", host, defport)
     }
 }
+
+/// match name against a pattern where `*` is any(maybe empty) string.
+pub fn name_match(pattern: &str, name: &str) -> bool {
+    let ps: Vec<&str> = pattern.split('*').collect();
+    if ps.len() == 1 {
+        return pattern == name;
+    }
+    let last = ps[ps.len() - 1];
+    if !name.starts_with(ps[0]) || name.len() < ps[0].len() + last.len() || !name.ends_with(last) {
+        return false;
+    }
+    let mut rest = &name[ps[0].len()..name.len() - last.len()];
+    for p in &ps[1..ps.len() - 1] {
+        match rest.find(p) {
+            None => return false,
+            Some(i) => rest = &rest[i + p.len()..],
+        }
+    }
+    true
+}
+
+/// an ip or a net like 10.0.0.0/8 or fd00::/8
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    bits: u8,
+}
+impl Cidr {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (ips, bits) = match s.trim().split_once('/') {
+            None => (s.trim(), None),
+            Some((ip, bits)) => (ip, Some(bits.parse::<u8>().ok()?)),
+        };
+        let addr: IpAddr = ips.parse().ok()?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let bits = bits.unwrap_or(max);
+        if bits > max {
+            return None;
+        }
+        Some(Self {
+            addr,
+            bits,
+        })
+    }
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        // a v4 client on a dual-stack listener shows as ::ffff:a.b.c.d
+        let ip = match ip {
+            IpAddr::V6(v) => v.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+            IpAddr::V4(_) => *ip,
+        };
+        match (&self.addr, &ip) {
+            (IpAddr::V4(a), IpAddr::V4(b)) => {
+                let mask = u32::MAX.checked_shl(32 - self.bits as u32).unwrap_or(0);
+                u32::from(*a) & mask == u32::from(*b) & mask
+            }
+            (IpAddr::V6(a), IpAddr::V6(b)) => {
+                let mask = u128::MAX.checked_shl(128 - self.bits as u32).unwrap_or(0);
+                u128::from(*a) & mask == u128::from(*b) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// deny wins,then an ip must be in allow unless allow is empty
+#[derive(Clone, Default)]
+pub struct IpAcl {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+}
+impl IpAcl {
+    pub fn parse(allow: &[String], deny: &[String]) -> io::Result<Self> {
+        let mut rts = Self::default();
+        for (ls, vs) in [(&mut rts.allow, allow), (&mut rts.deny, deny)] {
+            for v in vs {
+                match Cidr::parse(v.as_str()) {
+                    None => return Err(ruisutil::ioerr(format!("cidr err:{}", v), None)),
+                    Some(c) => ls.push(c),
+                }
+            }
+        }
+        Ok(rts)
+    }
+    /// None is a conn without an ip,like a node visitor
+    pub fn allowed(&self, ip: Option<&IpAddr>) -> bool {
+        let ip = match ip {
+            None => return self.allow.is_empty(),
+            Some(v) => v,
+        };
+        if self.deny.iter().any(|v| v.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|v| v.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::{mybytes, name_match, Cidr, IpAcl};
+
+    #[test]
+    fn name_matchs() {
+        assert!(name_match("edge-1", "edge-1"));
+        assert!(!name_match("edge-1", "edge-12"));
+        assert!(name_match("edge-*", "edge-12"));
+        assert!(name_match("edge-*", "edge-"));
+        assert!(!name_match("edge-*", "edg"));
+        assert!(name_match("*-db-*", "eu-db-1"));
+        assert!(!name_match("a*a", "a"));
+        assert!(name_match("*", "any"));
+    }
+
+    #[test]
+    fn mybytess() {
+        assert_eq!(mybytes(512), "512B");
+        assert_eq!(mybytes(1536), "1.5K");
+        assert_eq!(mybytes(3 * 1024 * 1024), "3.0M");
+        assert_eq!(mybytes(5 << 50), "5120.0T");
+    }
+
+    #[test]
+    fn ip_acls() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert!(Cidr::parse("10.0.0.0/8").unwrap().contains(&ip("10.1.2.3")));
+        assert!(!Cidr::parse("10.0.0.0/8").unwrap().contains(&ip("11.0.0.1")));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(&ip("8.8.8.8")));
+        assert!(Cidr::parse("192.168.1.7").unwrap().contains(&ip("192.168.1.7")));
+        assert!(Cidr::parse("fd00::/8").unwrap().contains(&ip("fd12::1")));
+        assert!(!Cidr::parse("fd00::/8").unwrap().contains(&ip("fe80::1")));
+        assert!(Cidr::parse("10.0.0.0/8").unwrap().contains(&ip("::ffff:10.0.0.1")));
+        assert!(!Cidr::parse("10.0.0.0/8").unwrap().contains(&ip("fd00::1")));
+        assert_eq!(Cidr::parse("10.0.0.0/33"), None);
+        assert_eq!(Cidr::parse("10.0.0/8"), None);
+
+        let acl = IpAcl::parse(
+            &["10.0.0.0/8".to_string(), "fd00::/8".to_string()],
+            &["10.0.9.0/24".to_string()],
+        )
+        .unwrap();
+        assert!(acl.allowed(Some(&ip("10.0.0.1"))));
+        assert!(acl.allowed(Some(&ip("fd00::2"))));
+        assert!(!acl.allowed(Some(&ip("10.0.9.1"))));
+        assert!(!acl.allowed(Some(&ip("1.1.1.1"))));
+        assert!(!acl.allowed(None));
+        let acl = IpAcl::parse(&[], &["1.1.1.1".to_string()]).unwrap();
+        assert!(acl.allowed(Some(&ip("1.1.1.2"))));
+        assert!(!acl.allowed(Some(&ip("1.1.1.1"))));
+        assert!(acl.allowed(None));
+    }
+}